@@ -0,0 +1,99 @@
+use anyhow::{anyhow, Result};
+use encoding_rs::{Encoding, UTF_8};
+use mime::Mime;
+
+// 按照以下优先级确定 body 的编码：
+//   1. --response-charset 指定的编码
+//   2. Content-Type 中的 charset 参数
+//   3. HTML 中的 <meta charset> 或者 XML 声明中的 encoding，只在类型是 HTML、XML 或者未知时查找
+//   4. UTF-8
+// 另外 body 以 BOM 开头时，总是以 BOM 表示的编码为准
+
+/// 解析 --response-charset 的值，支持 gbk、shift_jis、latin1 等常见的名字
+pub fn parse_charset(s: &str) -> Result<&'static Encoding> {
+    Encoding::for_label(s.as_bytes()).ok_or_else(|| anyhow!("unknown charset `{}`", s))
+}
+
+/// 确定 body 的编码，body 可以只是开头的一部分。解码时 Encoding::decode 和 Decoder 会自己去掉 BOM
+pub fn detect(body: &[u8], m: Option<&Mime>, forced: Option<&'static Encoding>) -> &'static Encoding {
+    Encoding::for_bom(body)
+        .map(|(encoding, _)| encoding)
+        .or(forced)
+        .or_else(|| from_mime(m?))
+        .or_else(|| m.is_none_or(is_markup).then(|| sniff(body)).flatten())
+        .unwrap_or(UTF_8)
+}
+
+/// 只有 HTML 和 XML 会在 body 中声明自己的编码，JSON 等文本中的 `<meta charset>` 只是普通的内容
+fn is_markup(m: &Mime) -> bool {
+    m.subtype() == mime::HTML || m.subtype() == mime::XML || m.suffix() == Some(mime::XML)
+}
+
+/// Content-Type 中的 charset 参数
+pub fn from_mime(m: &Mime) -> Option<&'static Encoding> {
+    let charset = m.get_param(mime::CHARSET)?;
+    Encoding::for_label(charset.as_str().as_bytes())
+}
+
+/// 从 body 的前 1KB 中查找 HTML 或 XML 自己声明的编码，这些声明总是 ASCII 字符，
+/// 因此可以先按照 ASCII 来查找
+fn sniff(body: &[u8]) -> Option<&'static Encoding> {
+    let head = String::from_utf8_lossy(&body[..body.len().min(1024)]).to_ascii_lowercase();
+    // <?xml version="1.0" encoding="gbk"?>
+    let xml = head
+        .trim_start()
+        .strip_prefix("<?xml")
+        .and_then(|decl| attr(decl.split("?>").next()?, "encoding="));
+    // <meta charset="gbk"> 或者 <meta http-equiv="Content-Type" content="text/html; charset=gbk">
+    let label = xml.or_else(|| {
+        head.split("<meta")
+            .skip(1)
+            .find_map(|tag| attr(tag.split('>').next()?, "charset="))
+    })?;
+    Encoding::for_label(label.as_bytes())
+}
+
+/// 取出 `key=value` 中的 value，value 可以带引号
+fn attr<'a>(s: &'a str, key: &str) -> Option<&'a str> {
+    let value = &s[s.find(key)? + key.len()..];
+    value
+        .trim_start_matches(['"', '\''])
+        .split(|c: char| c == '"' || c == '\'' || c == ';' || c == '/' || c.is_whitespace())
+        .next()
+        .filter(|v| !v.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use encoding_rs::{GBK, SHIFT_JIS, UTF_16LE, WINDOWS_1252};
+
+    fn detect_as(body: &str, m: Option<&str>) -> &'static Encoding {
+        let m: Option<Mime> = m.map(|m| m.parse().unwrap());
+        detect(body.as_bytes(), m.as_ref(), None)
+    }
+
+    #[test]
+    fn declared_charset_wins() {
+        assert_eq!(detect_as("<meta charset=gbk>", Some("text/html; charset=shift_jis")), SHIFT_JIS);
+        assert_eq!(detect(b"", Some(&mime::TEXT_PLAIN_UTF_8), Some(GBK)), GBK);
+    }
+
+    #[test]
+    fn bom_wins() {
+        assert_eq!(detect(b"\xff\xfeh\0", Some(&mime::TEXT_PLAIN_UTF_8), Some(GBK)), UTF_16LE);
+    }
+
+    #[test]
+    fn markup_is_sniffed() {
+        assert_eq!(detect_as("<html><meta charset=\"gbk\">", Some("text/html")), GBK);
+        assert_eq!(detect_as("<?xml version=\"1.0\" encoding='latin1'?><a/>", Some("image/svg+xml")), WINDOWS_1252);
+        assert_eq!(detect_as("<meta http-equiv=\"Content-Type\" content=\"text/html; charset=gbk\">", None), GBK);
+    }
+
+    #[test]
+    fn other_types_are_not_sniffed() {
+        assert_eq!(detect_as("{\"html\": \"<meta charset=gbk>\"}", Some("application/json")), UTF_8);
+        assert_eq!(detect_as("<meta charset=gbk>", Some("text/plain")), UTF_8);
+    }
+}
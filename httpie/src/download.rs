@@ -0,0 +1,234 @@
+use std::{
+    iter,
+    path::{Path, PathBuf},
+    time::Instant,
+};
+use anyhow::{anyhow, Context, Result};
+use indicatif::{ProgressBar, ProgressStyle};
+use reqwest::{header::{self, HeaderMap}, Request, Response, StatusCode, Url};
+use tokio::{fs::OpenOptions, io::AsyncWriteExt};
+
+// --download 模式：把响应的 body 以流的方式写入文件，而不是读入内存再打印到终端。
+// 数据每收到一块就写入文件，下载被中断时会留下一个不完整的文件，之后可以用 --continue 继续下载
+
+/// --continue 时，如果输出文件已经存在，就在请求中加上 Range 头，从文件末尾继续下载。
+/// 返回已经下载了的字节数
+pub async fn prepare(req: &mut Request, output: Option<&Path>, resume: bool) -> Result<u64> {
+    let output = match output {
+        Some(output) if resume => output,
+        _ => return Ok(0),
+    };
+    let downloaded = match tokio::fs::metadata(output).await {
+        Ok(meta) => meta.len(),
+        Err(_) => 0,
+    };
+    if downloaded > 0 {
+        let range = format!("bytes={}-", downloaded);
+        req.headers_mut().insert(header::RANGE, range.parse()?);
+    }
+    Ok(downloaded)
+}
+
+/// 把响应下载到文件中，文件名优先使用 -o 指定的，其次是 Content-Disposition 中的，最后从 URL 中获取
+pub async fn download(
+    mut resp: Response,
+    output: Option<&Path>,
+    downloaded: u64,
+) -> Result<()> {
+    let status = resp.status();
+    // 服务器认为 Range 超出了文件大小，说明之前已经下载完了
+    if downloaded > 0 && status == StatusCode::RANGE_NOT_SATISFIABLE {
+        eprintln!("Nothing to download, the file is already complete.");
+        return Ok(());
+    }
+    if !status.is_success() {
+        return Err(anyhow!("Download failed: {}", status));
+    }
+
+    // 只有服务器返回 206 时才是在续传，否则服务器返回的是整个文件，需要从头写起
+    let resumed = downloaded > 0 && status == StatusCode::PARTIAL_CONTENT;
+    if resumed {
+        // 服务器返回的部分必须正好从文件末尾开始，否则追加进去会把文件弄坏
+        let start = resp
+            .headers()
+            .get(header::CONTENT_RANGE)
+            .and_then(|v| v.to_str().ok())
+            .and_then(content_range_start);
+        if start != Some(downloaded) {
+            return Err(anyhow!(
+                "Cannot resume: the server did not send the content from byte {}, download again without --continue",
+                downloaded
+            ));
+        }
+    }
+    let path = match output {
+        Some(output) => output.to_path_buf(),
+        None => unique_path(&filename(resp.headers(), resp.url())),
+    };
+    let mut file = OpenOptions::new()
+        .create(true)
+        .write(true)
+        .append(resumed)
+        .truncate(!resumed)
+        .open(&path)
+        .await
+        .with_context(|| format!("Failed to open {}", path.display()))?;
+
+    let start = if resumed { downloaded } else { 0 };
+    let remaining = resp.content_length();
+    let progress = progress_bar(remaining.map(|len| start + len));
+    progress.set_position(start);
+    eprintln!("Downloading to {}", path.display());
+
+    let started = Instant::now();
+    let mut received = 0;
+    let interrupted = || {
+        format!("Download interrupted, {} is incomplete, use --continue to resume", path.display())
+    };
+    while let Some(chunk) = resp.chunk().await.with_context(interrupted)? {
+        file.write_all(&chunk).await?;
+        received += chunk.len() as u64;
+        progress.inc(chunk.len() as u64);
+    }
+    file.flush().await?;
+    progress.finish_and_clear();
+
+    if let Some(len) = remaining.filter(|len| received < *len) {
+        return Err(anyhow!(
+            "Incomplete download: received {} of {} bytes, use --continue to resume",
+            received,
+            len
+        ));
+    }
+    eprintln!(
+        "Done. {} saved to {} in {:.1}s",
+        indicatif::HumanBytes(start + received),
+        path.display(),
+        started.elapsed().as_secs_f64()
+    );
+    Ok(())
+}
+
+/// 知道文件大小时显示带速度和剩余时间的进度条，否则只显示已下载的大小和速度
+fn progress_bar(total: Option<u64>) -> ProgressBar {
+    match total {
+        Some(total) => {
+            let style = ProgressStyle::with_template(
+                "{bar:40.green/white} {bytes}/{total_bytes} {binary_bytes_per_sec} ETA {eta}",
+            )
+            .unwrap();
+            ProgressBar::new(total).with_style(style)
+        }
+        None => {
+            let style =
+                ProgressStyle::with_template("{spinner} {bytes} {binary_bytes_per_sec}").unwrap();
+            ProgressBar::new_spinner().with_style(style)
+        }
+    }
+}
+
+/// 解析 `bytes 100-199/200` 中的起始位置
+fn content_range_start(value: &str) -> Option<u64> {
+    let range = value.trim().strip_prefix("bytes ")?;
+    let (start, _) = range.trim_start().split_once('-')?;
+    start.parse().ok()
+}
+
+/// 从 Content-Disposition 或者 URL 的最后一段中获取文件名
+fn filename(headers: &HeaderMap, url: &Url) -> String {
+    let from_header = headers
+        .get(header::CONTENT_DISPOSITION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(disposition_filename);
+    let name = from_header.unwrap_or_else(|| url_filename(url));
+    // 只保留文件名部分，避免服务器通过 ../ 之类的名字把文件写到其它目录中
+    Path::new(&name)
+        .file_name()
+        .map(|n| n.to_string_lossy().into_owned())
+        .filter(|n| !n.is_empty())
+        .unwrap_or_else(|| "index".to_string())
+}
+
+/// 解析 `attachment; filename="report.pdf"` 中的文件名
+fn disposition_filename(value: &str) -> Option<String> {
+    value.split(';').find_map(|part| {
+        let (key, name) = part.trim().split_once('=')?;
+        let name = name.trim().trim_matches('"');
+        (key.trim().eq_ignore_ascii_case("filename") && !name.is_empty()).then(|| name.to_string())
+    })
+}
+
+fn url_filename(url: &Url) -> String {
+    url.path_segments()
+        .and_then(|mut segments| segments.next_back())
+        .filter(|s| !s.is_empty())
+        .unwrap_or("index")
+        .to_string()
+}
+
+/// 没有用 -o 指定文件名时不覆盖已有的文件，而是在文件名后面加上 -1、-2 这样的后缀
+fn unique_path(name: &str) -> PathBuf {
+    iter::once(PathBuf::from(name))
+        .chain((1..).map(|i| PathBuf::from(format!("{}-{}", name, i))))
+        .find(|p| !p.exists())
+        .unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{env, fs, process};
+
+    fn filename_of(disposition: Option<&str>, url: &str) -> String {
+        let mut headers = HeaderMap::new();
+        if let Some(value) = disposition {
+            headers.insert(header::CONTENT_DISPOSITION, value.parse().unwrap());
+        }
+        filename(&headers, &url.parse().unwrap())
+    }
+
+    #[test]
+    fn filename_from_disposition() {
+        assert_eq!(disposition_filename("attachment; filename=\"report.pdf\""), Some("report.pdf".into()));
+        assert_eq!(disposition_filename("attachment;FILENAME=a.txt"), Some("a.txt".into()));
+        assert_eq!(disposition_filename("attachment; filename=\"\""), None);
+        assert_eq!(disposition_filename("inline"), None);
+    }
+
+    #[test]
+    fn filename_falls_back_to_url() {
+        assert_eq!(filename_of(Some("attachment; filename=a.pdf"), "http://x/b.pdf"), "a.pdf");
+        assert_eq!(filename_of(None, "http://x/files/b.pdf?v=1"), "b.pdf");
+        assert_eq!(filename_of(None, "http://x/files/"), "index");
+        assert_eq!(filename_of(None, "http://x"), "index");
+    }
+
+    #[test]
+    fn filename_cannot_escape_the_directory() {
+        assert_eq!(filename_of(Some("attachment; filename=\"../../etc/passwd\""), "http://x/"), "passwd");
+        assert_eq!(filename_of(Some("attachment; filename=\"/tmp/evil.sh\""), "http://x/"), "evil.sh");
+        assert_eq!(filename_of(Some("attachment; filename=\"..\""), "http://x/"), "index");
+        assert_eq!(filename_of(None, "http://x/a/%2E%2E"), "index");
+    }
+
+    #[test]
+    fn unique_path_adds_a_suffix() {
+        let dir = env::temp_dir().join(format!("httpie-unique-path-{}", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let name = dir.join("a.txt").to_string_lossy().into_owned();
+        assert_eq!(unique_path(&name), PathBuf::from(&name));
+        fs::write(&name, "").unwrap();
+        assert_eq!(unique_path(&name), PathBuf::from(format!("{}-1", name)));
+        fs::write(format!("{}-1", name), "").unwrap();
+        assert_eq!(unique_path(&name), PathBuf::from(format!("{}-2", name)));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn content_range() {
+        assert_eq!(content_range_start("bytes 100-199/200"), Some(100));
+        assert_eq!(content_range_start("bytes 0-9/*"), Some(0));
+        assert_eq!(content_range_start("bytes */200"), None);
+        assert_eq!(content_range_start("items 1-2/3"), None);
+    }
+}
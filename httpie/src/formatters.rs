@@ -0,0 +1,297 @@
+use std::sync::OnceLock;
+use anyhow::{anyhow, Result};
+use mime::Mime;
+use quick_xml::{
+    events::{BytesText, Event},
+    Reader, Writer,
+};
+use serde_json::Value;
+
+// 根据 body 的 MIME 类型选择格式化器。匹配时只看 MIME 的 essence（去掉 charset 等参数之后的部分）
+// 和 +json / +xml 这样的后缀，因此 application/json; charset=utf-8、application/problem+json、
+// application/vnd.api+json 都会使用 JSON 格式化器
+
+/// 排版时的选项，和 HTTPie 一样通过 --format-options=json.indent:2,json.sort_keys:false 设置
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FormatOptions {
+    pub json_indent: usize,
+    pub json_sort_keys: bool,
+    pub headers_sort: bool,
+    pub xml_format: bool,
+    pub xml_indent: usize,
+}
+
+impl Default for FormatOptions {
+    fn default() -> Self {
+        Self {
+            json_indent: 4,
+            json_sort_keys: true,
+            headers_sort: true,
+            xml_format: true,
+            xml_indent: 2,
+        }
+    }
+}
+
+impl FormatOptions {
+    /// 应用一个 --format-options 的值，其中可以有多个以逗号分隔的 name:value
+    pub fn apply(&mut self, s: &str) -> Result<()> {
+        for option in s.split(',').map(str::trim).filter(|o| !o.is_empty()) {
+            let (name, value) = option
+                .split_once(':')
+                .ok_or_else(|| anyhow!("invalid format option `{}`, expected name:value", option))?;
+            let invalid = || anyhow!("invalid value for format option `{}`: {}", name, value);
+            match name {
+                "json.indent" => self.json_indent = value.parse().map_err(|_| invalid())?,
+                "json.sort_keys" => self.json_sort_keys = value.parse().map_err(|_| invalid())?,
+                "headers.sort" => self.headers_sort = value.parse().map_err(|_| invalid())?,
+                "xml.format" => self.xml_format = value.parse().map_err(|_| invalid())?,
+                "xml.indent" => self.xml_indent = value.parse().map_err(|_| invalid())?,
+                _ => return Err(anyhow!("unknown format option `{}`", name)),
+            }
+        }
+        Ok(())
+    }
+
+    /// --sorted / --unsorted，同时设置 JSON 字段和响应头的排序
+    pub fn sorted(self, sorted: bool) -> Self {
+        Self {
+            json_sort_keys: sorted,
+            headers_sort: sorted,
+            ..self
+        }
+    }
+}
+
+/// 一种内容类型的格式化器，它决定了 body 如何重新排版，以及使用哪种语法高亮
+pub trait Formatter: Send + Sync {
+    /// 是否能处理这种 MIME 类型
+    fn matches(&self, m: &Mime) -> bool;
+
+    /// 重新排版 body，缺省时原样返回
+    fn format(&self, body: &str, _opts: &FormatOptions) -> String {
+        body.to_string()
+    }
+
+    /// 高亮时使用的 syntect 语法，用扩展名表示，None 表示不高亮
+    fn syntax(&self) -> Option<&'static str>;
+}
+
+/// 按照注册的顺序依次匹配的格式化器列表，都匹配不上时使用纯文本格式化器
+pub struct Registry {
+    formatters: Vec<Box<dyn Formatter>>,
+}
+
+impl Default for Registry {
+    fn default() -> Self {
+        let mut registry = Self { formatters: Vec::new() };
+        // HTML 要在 XML 之前，这样 application/xhtml+xml 会按照 HTML 处理
+        registry.register(Json);
+        registry.register(Html);
+        registry.register(Xml);
+        registry.register(Css);
+        registry.register(JavaScript);
+        registry.register(Yaml);
+        registry
+    }
+}
+
+impl Registry {
+    /// 注册一个格式化器，后注册的优先级更低
+    pub fn register(&mut self, formatter: impl Formatter + 'static) {
+        self.formatters.push(Box::new(formatter));
+    }
+
+    /// 找到能处理该类型的格式化器，类型未知或者没有匹配的格式化器时使用纯文本
+    pub fn find(&self, m: Option<&Mime>) -> &dyn Formatter {
+        m.and_then(|m| self.formatters.iter().find(|f| f.matches(m)))
+            .map(|f| f.as_ref())
+            .unwrap_or(&Plain)
+    }
+}
+
+/// 进程内共用的缺省格式化器列表
+pub fn registry() -> &'static Registry {
+    static REGISTRY: OnceLock<Registry> = OnceLock::new();
+    REGISTRY.get_or_init(Registry::default)
+}
+
+/// MIME 的 essence 是否是 essences 之一，或者带有 suffix 后缀
+fn mime_matches(m: &Mime, essences: &[&str], suffix: Option<&str>) -> bool {
+    let essence = m.essence_str().to_ascii_lowercase();
+    essences.contains(&essence.as_str())
+        || suffix.is_some_and(|suffix| m.suffix().is_some_and(|s| s.as_str().eq_ignore_ascii_case(suffix)))
+}
+
+pub struct Json;
+
+impl Formatter for Json {
+    fn matches(&self, m: &Mime) -> bool {
+        mime_matches(
+            m,
+            &["application/json", "text/json", "application/x-ndjson", "application/jsonl"],
+            Some("json"),
+        )
+    }
+
+    /// 用 jsonxf 重新缩进，json.sort_keys 时先按照字段名排序。一个 body 中可以有多个 JSON 值，
+    /// 比如 NDJSON。body 不是合法的 JSON 时原样返回，避免把错误页面之类的内容排版乱
+    fn format(&self, body: &str, opts: &FormatOptions) -> String {
+        let values: Result<Vec<Value>, _> = serde_json::Deserializer::from_str(body).into_iter().collect();
+        let Ok(mut values) = values else {
+            return body.to_string();
+        };
+        let json = if opts.json_sort_keys {
+            values.iter_mut().for_each(sort_keys);
+            values.iter().map(Value::to_string).collect::<Vec<_>>().join("\n")
+        } else {
+            body.to_string()
+        };
+        let mut formatter = jsonxf::Formatter::pretty_printer();
+        formatter.indent = " ".repeat(opts.json_indent);
+        formatter.format(&json).unwrap_or(json)
+    }
+
+    fn syntax(&self) -> Option<&'static str> {
+        Some("json")
+    }
+}
+
+/// 递归地按照字段名排序
+fn sort_keys(value: &mut Value) {
+    match value {
+        Value::Object(map) => {
+            map.sort_keys();
+            map.values_mut().for_each(sort_keys);
+        }
+        Value::Array(arr) => arr.iter_mut().for_each(sort_keys),
+        _ => {}
+    }
+}
+
+pub struct Xml;
+
+impl Formatter for Xml {
+    fn matches(&self, m: &Mime) -> bool {
+        mime_matches(m, &["application/xml", "text/xml"], Some("xml"))
+    }
+
+    /// 按照元素的层级重新缩进，文档不完整或者格式错误时原样返回
+    fn format(&self, body: &str, opts: &FormatOptions) -> String {
+        if !opts.xml_format {
+            return body.to_string();
+        }
+        format_xml(body, opts.xml_indent).unwrap_or_else(|| body.to_string())
+    }
+
+    fn syntax(&self) -> Option<&'static str> {
+        Some("xml")
+    }
+}
+
+fn format_xml(body: &str, indent: usize) -> Option<String> {
+    let mut reader = Reader::from_str(body);
+    let mut writer = Writer::new_with_indent(Vec::new(), b' ', indent);
+    // 用来检查是否还有没有闭合的元素，quick-xml 在文档结束时不会检查这一点
+    let mut depth = 0usize;
+    loop {
+        let event = match reader.read_event().ok()? {
+            Event::Eof => break,
+            // 元素之间只用于排版的空白会重新生成，其它文本原样保留
+            Event::Text(text) if text.as_ref().trim().is_empty() => continue,
+            // 实体引用和文本写在同一行，否则 `a &amp; b` 会被拆成多行
+            Event::GeneralRef(r) => {
+                let name: &str = r.as_ref();
+                Event::Text(BytesText::from_escaped(format!("&{};", name)))
+            }
+            event => event,
+        };
+        match event {
+            Event::Start(_) => depth += 1,
+            Event::End(_) => depth = depth.checked_sub(1)?,
+            _ => {}
+        }
+        writer.write_event(event).ok()?;
+    }
+    if depth != 0 {
+        return None;
+    }
+    let mut xml = String::from_utf8(writer.into_inner()).ok()?;
+    xml.push('\n');
+    Some(xml)
+}
+
+pub struct Html;
+
+impl Formatter for Html {
+    fn matches(&self, m: &Mime) -> bool {
+        mime_matches(m, &["text/html", "application/xhtml+xml"], None)
+    }
+
+    fn syntax(&self) -> Option<&'static str> {
+        Some("html")
+    }
+}
+
+pub struct Css;
+
+impl Formatter for Css {
+    fn matches(&self, m: &Mime) -> bool {
+        mime_matches(m, &["text/css"], None)
+    }
+
+    fn syntax(&self) -> Option<&'static str> {
+        Some("css")
+    }
+}
+
+pub struct JavaScript;
+
+impl Formatter for JavaScript {
+    fn matches(&self, m: &Mime) -> bool {
+        mime_matches(
+            m,
+            &[
+                "application/javascript",
+                "text/javascript",
+                "application/ecmascript",
+                "text/ecmascript",
+                "application/x-javascript",
+            ],
+            None,
+        )
+    }
+
+    fn syntax(&self) -> Option<&'static str> {
+        Some("js")
+    }
+}
+
+pub struct Yaml;
+
+impl Formatter for Yaml {
+    fn matches(&self, m: &Mime) -> bool {
+        mime_matches(
+            m,
+            &["application/yaml", "application/x-yaml", "text/yaml", "text/x-yaml"],
+            Some("yaml"),
+        )
+    }
+
+    fn syntax(&self) -> Option<&'static str> {
+        Some("yaml")
+    }
+}
+
+/// 纯文本，原样输出，不做高亮
+pub struct Plain;
+
+impl Formatter for Plain {
+    fn matches(&self, _m: &Mime) -> bool {
+        true
+    }
+
+    fn syntax(&self) -> Option<&'static str> {
+        None
+    }
+}
@@ -0,0 +1,420 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+use anyhow::{anyhow, Context, Result};
+use mime::Mime;
+use reqwest::{
+    header::{HeaderName, HeaderValue},
+    multipart::{Form, Part},
+    Body,
+};
+use serde_json::Value;
+use tokio_util::io::ReaderStream;
+
+use crate::nested_json::{self, syntax_error};
+
+// 命令行中的请求项，和 HTTPie 一样由分隔符决定它会被放到请求的哪一部分：
+//   Header:Value     请求头
+//   Header:@file     请求头，值从文件中读取
+//   param==value     URL 中的 query 参数
+//   field=value      body 中的字符串字段
+//   field=@file      body 中的字符串字段，值为文件内容
+//   field:=json      body 中的原始 JSON 字段，如 age:=30
+//   field:=@file     body 中的 JSON 字段，值从 JSON 文件中读取
+//   field@file       multipart 上传的文件，可以用 field@file;type=image/png 指定文件类型
+//   @file            把整个文件作为原始的 body 发送
+
+/// 一个请求项
+#[derive(Debug, Clone, PartialEq)]
+pub enum RequestItem {
+    Header(String, String),
+    HeaderFile(String, PathBuf),
+    Query(String, String),
+    Field(String, String),
+    FieldFile(String, PathBuf),
+    Json(String, Value),
+    JsonFile(String, PathBuf),
+    File(String, PathBuf, Option<Mime>),
+    /// `@file`，把整个文件作为请求的 body
+    BodyFile(PathBuf),
+}
+
+// 所有支持的分隔符。同一位置上可能匹配多个分隔符（比如 `:=@` / `:=` / `:`），
+// 因此这里按长度从长到短排列，保证同一位置总是优先匹配最长的那个
+const SEPARATORS: [&str; 8] = [":=@", ":=", ":@", "==", "=@", ":", "=", "@"];
+
+// key 中可以用反斜杠转义的字符，转义之后它们不再被当作分隔符，比如 `a\=b=c` 的 key 是 `a=b`
+const ESCAPABLE: [char; 3] = ['=', ':', '@'];
+
+/// 把请求项切分成 key、分隔符和 value。只在第一个没有被转义的分隔符处切分，
+/// 因此 value 中可以随意出现 `=` 和 `:`，比如 `token=a=b` 的值是 `a=b`
+fn tokenize(s: &str) -> Result<(String, &'static str, &str)> {
+    let mut key = String::new();
+    let mut chars = s.char_indices().peekable();
+    while let Some((i, c)) = chars.next() {
+        if c == '\\' {
+            // 只有分隔符中的字符需要转义，其它的反斜杠原样保留
+            if let Some(&(_, next)) = chars.peek().filter(|(_, next)| ESCAPABLE.contains(next)) {
+                key.push(next);
+                chars.next();
+                continue;
+            }
+        } else if let Some(sep) = SEPARATORS.iter().find(|sep| s[i..].starts_with(*sep)) {
+            if key.is_empty() {
+                let msg = format!("missing key before `{}`", sep);
+                return Err(syntax_error(s, i, sep.len(), &msg));
+            }
+            return Ok((key, sep, &s[i + sep.len()..]));
+        }
+        key.push(c);
+    }
+    Err(syntax_error(
+        s,
+        s.len(),
+        0,
+        "expected a separator: one of `:`, `==`, `=`, `:=`, `=@`, `:=@`, `:@`, `@`",
+    ))
+}
+
+/// 是否是一个请求项（有分隔符），它的内容不一定合法，用于区分 URL 和请求项
+pub fn is_item(s: &str) -> bool {
+    s.starts_with('@') || tokenize(s).is_ok()
+}
+
+impl FromStr for RequestItem {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        // 只有 @file 这一种请求项是没有 key 的
+        if let Some(path) = s.strip_prefix('@') {
+            return Ok(Self::BodyFile(path.into()));
+        }
+        let (key, sep, value) = tokenize(s)?;
+        let value_start = s.len() - value.len();
+        if sep.starts_with(':') && !sep.starts_with(":=") && HeaderName::from_bytes(key.as_bytes()).is_err() {
+            let key_len = value_start - sep.len();
+            return Err(syntax_error(s, 0, key_len, "invalid header name"));
+        }
+        Ok(match sep {
+            ":" => {
+                if HeaderValue::from_str(value).is_err() {
+                    let msg = "invalid header value, control characters are not allowed";
+                    return Err(syntax_error(s, value_start, value.len(), msg));
+                }
+                Self::Header(key, value.to_string())
+            }
+            ":@" => Self::HeaderFile(key, value.into()),
+            "==" => Self::Query(key, value.to_string()),
+            "=" => Self::Field(key, value.to_string()),
+            "=@" => Self::FieldFile(key, value.into()),
+            ":=" => {
+                let json = serde_json::from_str(value).map_err(|e| {
+                    // 把 serde_json 报告的行列换算成在整个请求项中的位置
+                    let offset: usize = value
+                        .split_inclusive('\n')
+                        .take(e.line().saturating_sub(1))
+                        .map(str::len)
+                        .sum();
+                    let pos = (value_start + offset + e.column().saturating_sub(1)).min(s.len());
+                    let pos = (0..=pos).rev().find(|&p| s.is_char_boundary(p)).unwrap_or(0);
+                    syntax_error(s, pos, 0, &format!("invalid JSON value: {}", e))
+                })?;
+                Self::Json(key, json)
+            }
+            ":=@" => Self::JsonFile(key, value.into()),
+            "@" => match value.rsplit_once(";type=") {
+                Some((path, mime)) => {
+                    let mime = mime.parse().map_err(|e| {
+                        let pos = s.len() - mime.len();
+                        syntax_error(s, pos, mime.len(), &format!("invalid MIME type: {}", e))
+                    })?;
+                    Self::File(key, path.into(), Some(mime))
+                }
+                None => Self::File(key, value.into(), None),
+            },
+            _ => unreachable!(),
+        })
+    }
+}
+
+impl RequestItem {
+    /// 这一项是否属于请求的 body
+    pub fn is_data(&self) -> bool {
+        matches!(
+            self,
+            Self::Field(..)
+                | Self::FieldFile(..)
+                | Self::Json(..)
+                | Self::JsonFile(..)
+                | Self::File(..)
+        )
+    }
+
+    /// 读取文件等，得到 body 字段的 key 和 JSON 值，非 body 项返回 None
+    pub fn data(&self) -> Result<Option<(&str, Value)>> {
+        Ok(match self {
+            Self::Field(k, v) => Some((k, Value::String(v.clone()))),
+            Self::FieldFile(k, path) => Some((k, Value::String(read_file(path)?))),
+            Self::Json(k, v) => Some((k, v.clone())),
+            Self::JsonFile(k, path) => {
+                let json = serde_json::from_str(&read_file(path)?)
+                    .with_context(|| format!("Failed to parse {}: invalid JSON", path.display()))?;
+                Some((k, json))
+            }
+            _ => None,
+        })
+    }
+
+    /// 得到请求头的名字和值，非请求头项返回 None
+    pub fn header(&self) -> Result<Option<(HeaderName, HeaderValue)>> {
+        let (name, value) = match self {
+            Self::Header(k, v) => (k, v.clone()),
+            // 文件末尾的换行不应该出现在请求头里
+            Self::HeaderFile(k, path) => (k, read_file(path)?.trim_end().to_string()),
+            _ => return Ok(None),
+        };
+        let name = HeaderName::from_bytes(name.as_bytes())
+            .with_context(|| format!("invalid header name `{}`", name))?;
+        let value = HeaderValue::from_str(&value)
+            .with_context(|| format!("invalid value for header `{}`", name))?;
+        Ok(Some((name, value)))
+    }
+}
+
+fn read_file(path: &PathBuf) -> Result<String> {
+    fs::read_to_string(path).with_context(|| format!("Failed to read {}", path.display()))
+}
+
+/// 把所有 body 字段组装成一个 JSON 值，字段的顺序和命令行中的顺序保持一致。
+/// `=` 得到的是字符串，`:=` 则保留原始 JSON 的类型，因此 `age:=30 admin:=true` 会得到数字和布尔值。
+/// key 中可以用 `user[name]`、`tags[]` 这样的语法构造嵌套的结构，见 nested_json。
+/// 没有任何 body 字段时返回 None
+pub fn json_body(items: &[RequestItem]) -> Result<Option<Value>> {
+    let mut body = Value::Null;
+    for item in items {
+        if let Some((name, value)) = item.data()? {
+            nested_json::insert(&mut body, name, value)?;
+        }
+    }
+    Ok((!body.is_null()).then_some(body))
+}
+
+/// 把所有 body 字段组装成表单，表单中的值只能是字符串，因此不支持 `:=` 这样的 JSON 字段，
+/// 而 `user[name]` 这样的 key 会原样发送
+pub fn form_body(items: &[RequestItem]) -> Result<Vec<(&str, String)>> {
+    let mut form = Vec::new();
+    for item in items {
+        if let Some(field) = form_field(item)? {
+            form.push(field);
+        }
+    }
+    Ok(form)
+}
+
+/// 把 body 字段组装成 multipart/form-data，`field@path` 上传文件，其它字段作为文本
+pub async fn multipart_body(items: &[RequestItem]) -> Result<Form> {
+    let mut form = Form::new();
+    for item in items {
+        if let RequestItem::File(name, path, mime) = item {
+            form = form.part(name.clone(), file_part(path, mime.as_ref()).await?);
+        } else if let Some((name, value)) = form_field(item)? {
+            form = form.text(name.to_string(), value);
+        }
+    }
+    Ok(form)
+}
+
+/// 只有文本字段时直接把 multipart body 拼成字节，这样 --offline 和 --verbose 可以打印出它的内容。
+/// 返回带 boundary 的 Content-Type 和 body，有文件字段时返回 None，由 multipart_body 以流的方式发送
+pub fn multipart_text_body(items: &[RequestItem]) -> Result<Option<(String, Vec<u8>)>> {
+    if items.iter().any(|i| matches!(i, RequestItem::File(..))) {
+        return Ok(None);
+    }
+    // 借用 reqwest 生成的随机 boundary
+    let boundary = Form::new().boundary().to_string();
+    let mut body = Vec::new();
+    for item in items {
+        if let Some((name, value)) = form_field(item)? {
+            // 和浏览器一样，字段名中的引号和换行用百分号编码
+            let name = name.replace('"', "%22").replace('\r', "%0D").replace('\n', "%0A");
+            body.extend_from_slice(format!("--{}\r\n", boundary).as_bytes());
+            body.extend_from_slice(format!("Content-Disposition: form-data; name=\"{}\"\r\n\r\n", name).as_bytes());
+            body.extend_from_slice(value.as_bytes());
+            body.extend_from_slice(b"\r\n");
+        }
+    }
+    body.extend_from_slice(format!("--{}--\r\n", boundary).as_bytes());
+    Ok(Some((format!("multipart/form-data; boundary={}", boundary), body)))
+}
+
+/// 表单中的一个文本字段
+fn form_field(item: &RequestItem) -> Result<Option<(&str, String)>> {
+    match item {
+        RequestItem::Json(k, _) | RequestItem::JsonFile(k, _) => Err(anyhow!(
+            "JSON field `{}` cannot be sent as a form, remove --form to send JSON",
+            k
+        )),
+        _ => Ok(match item.data()? {
+            Some((name, Value::String(value))) => Some((name, value)),
+            _ => None,
+        }),
+    }
+}
+
+/// 以流的方式读取文件，上传大文件时不需要把它整个读入内存
+async fn file_part(path: &Path, mime: Option<&Mime>) -> Result<Part> {
+    let file = tokio::fs::File::open(path)
+        .await
+        .with_context(|| format!("Failed to open {}", path.display()))?;
+    let len = file.metadata().await?.len();
+    let mime = mime
+        .cloned()
+        .or_else(|| guess_mime(path))
+        .unwrap_or(mime::APPLICATION_OCTET_STREAM);
+    let name = path
+        .file_name()
+        .map(|n| n.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let part = Part::stream_with_length(Body::wrap_stream(ReaderStream::new(file)), len)
+        .file_name(name)
+        .mime_str(mime.as_ref())?;
+    Ok(part)
+}
+
+// 常见文件扩展名对应的 MIME 类型
+const MIME_TYPES: [(&str, &str); 27] = [
+    ("json", "application/json"),
+    ("xml", "application/xml"),
+    ("pdf", "application/pdf"),
+    ("zip", "application/zip"),
+    ("gz", "application/gzip"),
+    ("tar", "application/x-tar"),
+    ("wasm", "application/wasm"),
+    ("yaml", "application/yaml"),
+    ("yml", "application/yaml"),
+    ("js", "text/javascript"),
+    ("txt", "text/plain"),
+    ("csv", "text/csv"),
+    ("html", "text/html"),
+    ("htm", "text/html"),
+    ("css", "text/css"),
+    ("md", "text/markdown"),
+    ("png", "image/png"),
+    ("jpg", "image/jpeg"),
+    ("jpeg", "image/jpeg"),
+    ("gif", "image/gif"),
+    ("bmp", "image/bmp"),
+    ("svg", "image/svg+xml"),
+    ("webp", "image/webp"),
+    ("ico", "image/x-icon"),
+    ("mp3", "audio/mpeg"),
+    ("wav", "audio/wav"),
+    ("mp4", "video/mp4"),
+];
+
+/// 根据文件扩展名猜测 MIME 类型，猜不出来时返回 None，由调用者决定使用什么类型
+pub fn guess_mime(path: &Path) -> Option<Mime> {
+    let ext = path
+        .extension()
+        .and_then(|e| e.to_str())
+        .map(|e| e.to_ascii_lowercase());
+    MIME_TYPES
+        .iter()
+        .find(|(e, _)| Some(*e) == ext.as_deref())
+        .and_then(|(_, m)| m.parse().ok())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn parse(s: &str) -> RequestItem {
+        s.parse().unwrap()
+    }
+
+    fn error(s: &str) -> String {
+        s.parse::<RequestItem>().unwrap_err().to_string()
+    }
+
+    #[test]
+    fn every_separator() {
+        assert_eq!(parse("X-A:1"), RequestItem::Header("X-A".into(), "1".into()));
+        assert_eq!(parse("X-A:@a.txt"), RequestItem::HeaderFile("X-A".into(), "a.txt".into()));
+        assert_eq!(parse("q==1"), RequestItem::Query("q".into(), "1".into()));
+        assert_eq!(parse("f=1"), RequestItem::Field("f".into(), "1".into()));
+        assert_eq!(parse("f=@a.txt"), RequestItem::FieldFile("f".into(), "a.txt".into()));
+        assert_eq!(parse("n:=[1, true]"), RequestItem::Json("n".into(), json!([1, true])));
+        assert_eq!(parse("n:=@a.json"), RequestItem::JsonFile("n".into(), "a.json".into()));
+        assert_eq!(parse("f@a.png"), RequestItem::File("f".into(), "a.png".into(), None));
+        assert_eq!(
+            parse("f@a.bin;type=image/png"),
+            RequestItem::File("f".into(), "a.bin".into(), Some(mime::IMAGE_PNG))
+        );
+        assert_eq!(parse("@body.json"), RequestItem::BodyFile("body.json".into()));
+    }
+
+    #[test]
+    fn splits_on_the_first_separator_only() {
+        assert_eq!(parse("token=a=b"), RequestItem::Field("token".into(), "a=b".into()));
+        assert_eq!(parse("pad=YQ=="), RequestItem::Field("pad".into(), "YQ==".into()));
+        assert_eq!(parse("Referer:http://x"), RequestItem::Header("Referer".into(), "http://x".into()));
+        assert_eq!(parse("a==b==c"), RequestItem::Query("a".into(), "b==c".into()));
+    }
+
+    #[test]
+    fn escaped_separators_in_keys() {
+        assert_eq!(parse(r"a\=b=c"), RequestItem::Field("a=b".into(), "c".into()));
+        assert_eq!(parse(r"a\:b==c"), RequestItem::Query("a:b".into(), "c".into()));
+        assert_eq!(parse(r"me\@host=x"), RequestItem::Field("me@host".into(), "x".into()));
+        // 其它字符前面的反斜杠原样保留
+        assert_eq!(parse(r"a\nb=c"), RequestItem::Field(r"a\nb".into(), "c".into()));
+    }
+
+    #[test]
+    fn mime_is_guessed_from_the_extension() {
+        assert_eq!(guess_mime(Path::new("a/b.JSON")), Some(mime::APPLICATION_JSON));
+        assert_eq!(guess_mime(Path::new("body.txt.bak")), None);
+        assert_eq!(guess_mime(Path::new("Makefile")), None);
+    }
+
+    #[test]
+    fn is_item_requires_a_separator() {
+        assert!(is_item("a=1"));
+        assert!(is_item("@file"));
+        assert!(is_item("bad header:x"));
+        assert!(!is_item("example.com"));
+    }
+
+    #[test]
+    fn errors_point_at_the_problem() {
+        assert_eq!(
+            error("abc"),
+            "expected a separator: one of `:`, `==`, `=`, `:=`, `=@`, `:=@`, `:@`, `@`\n  abc\n     ^"
+        );
+        assert_eq!(error("=x"), "missing key before `=`\n  =x\n  ^");
+        assert_eq!(error(":=1"), "missing key before `:=`\n  :=1\n  ^^");
+        assert_eq!(
+            error("n:={\"a\": }"),
+            "invalid JSON value: expected value at line 1 column 7\n  n:={\"a\": }\n           ^"
+        );
+        assert_eq!(
+            error("f@a.bin;type=nope"),
+            "invalid MIME type: mime parse error: a slash (/) was missing between the type and subtype\n  \
+             f@a.bin;type=nope\n               ^^^^"
+        );
+    }
+
+    #[test]
+    fn header_names_and_values_are_validated() {
+        assert_eq!(error("bad header:x"), "invalid header name\n  bad header:x\n  ^^^^^^^^^^");
+        assert_eq!(
+            error("X-A:a\u{1}b"),
+            "invalid header value, control characters are not allowed\n  X-A:a\u{1}b\n      ^^^"
+        );
+        // JSON 字段的 key 不是请求头
+        assert_eq!(parse("bad key:=1"), RequestItem::Json("bad key".into(), json!(1)));
+    }
+}
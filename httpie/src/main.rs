@@ -1,210 +1,558 @@
-use std::{collections::HashMap, str::FromStr};
-use clap::Parser;
-use colored::Colorize;
-use reqwest::{header, Client, Response, Url};
-use anyhow::{anyhow, Result};
-use mime::Mime;
-use syntect::{
-    easy::HighlightLines,
-    highlighting::{ThemeSet, Style},
-    parsing::SyntaxSet,
-    util::{as_24_bit_terminal_escaped, LinesWithEndings},
+mod charset;
+mod download;
+mod formatters;
+mod items;
+mod nested_json;
+mod output;
+mod terminal;
+mod theme;
+
+use std::{
+    env, fs,
+    io::{self, IsTerminal, Read},
+    path::PathBuf,
+    time::Instant,
 };
+use clap::{ArgAction, Parser};
+use encoding_rs::Encoding;
+use reqwest::{header, Client, Method, Request, Url};
+use anyhow::{anyhow, Context, Result};
+use items::RequestItem;
+use mime::Mime;
+use formatters::FormatOptions;
+use terminal::ColorDepth;
+use output::{Pretty, PrintOptions};
 
 
-// 定义 HTTPie 的 CLI 的主入口，它包含若干个子命令
+// 定义 HTTPie 的 CLI 的主入口，语法和 HTTPie 保持一致：[METHOD] URL [ITEM...]
 // 下面 /// 的注释是文档，clap 会将其作为 CLI 的帮助
 
 /// A naive httpie implementation with Rust, can you imagine how easy it is?
 #[derive(Parser, Debug)]
-#[clap(version = "1.0", author = "Tyr Chen <tyr@chen.com>")]
+// -h 被用作 --headers 的简写，因此关掉 clap 自带的 -h，只保留 --help
+#[clap(version = "1.0", author = "Tyr Chen <tyr@chen.com>", disable_help_flag = true)]
 struct Opts {
-    #[clap(subcommand)]
-    subcmd: SubCommand,
+    /// 可选的 HTTP 方法（缺省时有 body 用 POST，否则用 GET）、URL 以及若干个请求项：
+    /// Header:Value、param==value、field=value、field:=json、field=@file、field:=@file.json、Header:@file、
+    /// field@file（上传文件）、@file（整个文件作为 body）
+    #[clap(required_unless_present = "list_styles", value_name = "[METHOD] URL [ITEM]")]
+    args: Vec<String>,
+    /// 把 body 字段序列化成 JSON 发送（默认）
+    #[clap(short, long, conflicts_with_all = ["form", "multipart"])]
+    json: bool,
+    /// 把 body 字段作为 application/x-www-form-urlencoded 表单发送，有 field@path 时使用 multipart
+    #[clap(short, long)]
+    form: bool,
+    /// 总是以 multipart/form-data 格式发送 body 字段
+    #[clap(long)]
+    multipart: bool,
+    /// 直接把这段文本作为请求的 body 发送
+    #[clap(long, value_name = "BODY")]
+    raw: Option<String>,
+    /// 不要把管道输入的 stdin 作为请求的 body，在脚本中使用时可以避免阻塞在读取 stdin 上
+    #[clap(short = 'I', long)]
+    ignore_stdin: bool,
+    /// URL 中没有 scheme 时使用的 scheme，以 https 的名字运行时默认为 https，否则为 http
+    #[clap(long, value_name = "SCHEME")]
+    default_scheme: Option<String>,
+    /// 只构造并打印请求，不真正发送它
+    #[clap(long)]
+    offline: bool,
+    /// 在打印响应之前，先打印发送的请求
+    #[clap(short, long)]
+    verbose: bool,
+    /// 选择要打印的部分：H 请求头，B 请求 body，h 响应头，b 响应 body，m 耗时等元信息。
+    /// 缺省时终端中打印 hb，输出被重定向时只打印 b
+    #[clap(short, long, value_name = "WHAT", conflicts_with_all = ["headers", "body"])]
+    print: Option<PrintOptions>,
+    /// 只打印响应头，等同于 --print=h
+    #[clap(short, long, conflicts_with = "body")]
+    headers: bool,
+    /// 只打印响应 body，等同于 --print=b
+    #[clap(short, long)]
+    body: bool,
+    /// 把响应的 body 下载到文件中，并显示下载进度
+    #[clap(short, long)]
+    download: bool,
+    /// 下载时保存到的文件，缺省时使用 Content-Disposition 或 URL 中的文件名
+    #[clap(short, long, value_name = "FILE", requires = "download")]
+    output: Option<PathBuf>,
+    /// 从已经存在的 --output 文件末尾继续下载
+    #[clap(short = 'c', long = "continue", requires = "output")]
+    resume: bool,
+    /// 边接收边逐行打印响应的 body，适用于日志、NDJSON 等持续输出的响应
+    #[clap(short = 'S', long)]
+    stream: bool,
+    /// 即使在终端中，也原样输出二进制的 body
+    #[clap(long)]
+    binary: bool,
+    /// 用指定的编码解码响应，忽略 Content-Type 中的 charset，如 gbk、shift_jis、latin1
+    #[clap(long, value_name = "CHARSET", value_parser = charset::parse_charset)]
+    response_charset: Option<&'static Encoding>,
+    /// 把响应当作指定的类型来格式化和高亮，忽略 Content-Type，如 application/json
+    #[clap(long, value_name = "MIME")]
+    response_mime: Option<Mime>,
+    /// 排版选项，如 json.indent:2,json.sort_keys:false,headers.sort:false，可以使用多次
+    #[clap(long, value_name = "OPTIONS")]
+    format_options: Vec<String>,
+    /// 对 JSON 的字段和响应头排序，等同于 --format-options=json.sort_keys:true,headers.sort:true
+    #[clap(long, conflicts_with = "unsorted")]
+    sorted: bool,
+    /// 不对 JSON 的字段和响应头排序，保持服务器返回的顺序
+    #[clap(long)]
+    unsorted: bool,
+    /// 输出的美化方式，是否使用颜色以及是否重新排版 body。缺省时终端中使用颜色并重新排版，输出被重定向时原样输出；
+    /// 没有指定时设置了 NO_COLOR 则不使用颜色，否则设置了 FORCE_COLOR 则使用颜色，指定了 --pretty 时忽略这两个环境变量
+    #[clap(long, value_enum, value_name = "STYLE")]
+    pretty: Option<Pretty>,
+    /// 超过这个字节数的 body 只排版不高亮，以便很快地打印很大的响应
+    #[clap(long, value_name = "BYTES", default_value_t = 1024 * 1024)]
+    max_highlight_size: usize,
+    /// 高亮使用的主题，如 InspiredGitHub、Solarized (light)，也可以是配置目录 themes/ 下的 .tmTheme 文件名
+    #[clap(long, value_name = "NAME", default_value = theme::DEFAULT_STYLE)]
+    style: String,
+    /// 列出 --style 可以使用的所有主题
+    #[clap(long)]
+    list_styles: bool,
+    /// 打印帮助信息
+    #[clap(long, action = ArgAction::Help)]
+    help: Option<bool>,
 }
 
-// 子命令分别对应不同的 HTTP 方法，目前只支持 get / post
-#[derive(Parser, Debug)]
-enum SubCommand {
-    Get(Get),
-    Post(Post),
-    // 我们暂且不支持其它 HTTP 方法
-}
-
-// get 子命令
+impl Opts {
+    /// 把程序链接或者复制成 https 来运行时，缺省使用 https
+    fn default_scheme(&self) -> String {
+        if let Some(scheme) = &self.default_scheme {
+            return scheme.clone();
+        }
+        let is_https = env::args_os()
+            .next()
+            .map(PathBuf::from)
+            .and_then(|p| p.file_stem().map(|s| s == "https"))
+            .unwrap_or(false);
+        if is_https { "https" } else { "http" }.to_string()
+    }
 
-/// feed get with an url and we will retrieve the response for you
-#[derive(Parser, Debug)]
-struct Get {
-    /// HTTP 请求的 URL
-    #[clap(value_parser = parse_url)]
-    url: String,
+    /// 根据 --print 等参数决定打印哪些部分，没有指定时 --offline 打印请求，
+    /// --verbose 打印请求和响应，否则只打印响应，输出被重定向时只打印响应的 body
+    fn print_options(&self) -> Result<PrintOptions> {
+        let is_terminal = io::stdout().is_terminal();
+        let what = match &self.print {
+            Some(print) => print.clone(),
+            // 下载时 body 写入文件，终端中只打印响应头
+            None if self.headers || self.download => "h".parse().unwrap(),
+            None if self.body => "b".parse().unwrap(),
+            None if self.offline => "HB".parse().unwrap(),
+            None if self.verbose => "HBhb".parse().unwrap(),
+            None if is_terminal => "hb".parse().unwrap(),
+            None => "b".parse().unwrap(),
+        };
+        let mut format = FormatOptions::default();
+        for options in &self.format_options {
+            format.apply(options)?;
+        }
+        if self.sorted || self.unsorted {
+            format = format.sorted(self.sorted);
+        }
+        let (colors, reformat) = match self.pretty {
+            Some(pretty) => (pretty.colors(), pretty.format()),
+            None => (terminal::use_colors(is_terminal), is_terminal),
+        };
+        // colored 自己也会检查终端和 NO_COLOR，这里统一由上面的规则决定
+        colored::control::set_override(colors);
+        let what = what
+            .with_pretty(colors.then(ColorDepth::detect), reformat)
+            .with_highlight_limit(self.max_highlight_size)
+            .with_binary(self.binary || !is_terminal)
+            .with_response_overrides(self.response_charset, self.response_mime.clone())
+            .with_format(format);
+        // 只有使用颜色时才需要主题，这样不用颜色时不会去读取配置目录中的主题
+        if colors {
+            Ok(what.with_theme(theme::load(&self.style)?))
+        } else {
+            Ok(what)
+        }
+    }
 }
 
-// post 子命令。需要输入一个 URL，和若干个可选的 key=value，用于提供 json body
+/// body 字段的发送格式
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum BodyKind {
+    Json,
+    Form,
+    Multipart,
+}
 
-/// feed post with an url and optional key=value pairs. We will post the data
-/// as JSON, and retrieve the response for you
-#[derive(Parser, Debug)]
-struct Post {
-    /// HTTP 请求的 URL
-    #[clap(value_parser = parse_url)]
-    url: String,
-    /// HTTP 请求的 body
-    #[clap(value_parser = parse_kv_pair)]
-    body: Vec<KvPair>,
+/// 直接发送的原始 body，它来自 stdin、--raw 或者 @file，而不是由请求项组装而来
+#[derive(Debug)]
+struct RawBody {
+    data: Vec<u8>,
+    /// 优先使用根据文件扩展名猜测的类型，其次是显式指定的 --json / --form，都没有时不设置 Content-Type
+    mime: Option<Mime>,
 }
 
-#[derive(Debug, PartialEq, Clone)]
-struct KvPair {
-    k: String,
-    v: String,
+/// 从命令行参数中解析出来的一个 HTTP 请求
+#[derive(Debug)]
+struct RequestArgs {
+    method: Method,
+    url: Url,
+    items: Vec<RequestItem>,
+    body_kind: BodyKind,
+    raw_body: Option<RawBody>,
 }
 
-impl FromStr for KvPair {
-    type Err = anyhow::Error;
+impl TryFrom<&Opts> for RequestArgs {
+    type Error = anyhow::Error;
 
-    fn from_str(s: &str) -> Result<Self, Self::Err> {
-        // 使用 = 进行split，这会得到一个迭代器
-        let mut split = s.split('=');
-        let err = || anyhow!("Failed to parse {}", s);
+    fn try_from(opts: &Opts) -> Result<Self, Self::Error> {
+        let args = &opts.args[..];
+        // 第一个参数可能是方法，也可能直接就是 URL
+        let (method, rest) = match args {
+            [first, second, ..] if is_method(first, second) => (Some(first), &args[1..]),
+            _ => (None, args),
+        };
+        let (url, items) = rest.split_first().ok_or_else(|| anyhow!("URL is required"))?;
+        let mut url = parse_url(url, &opts.default_scheme())?;
+        let items = items
+            .iter()
+            .map(|s| s.parse())
+            .collect::<Result<Vec<RequestItem>>>()?;
+        append_query(&mut url, &items);
+        let has_data = items.iter().any(RequestItem::is_data);
+        let raw_body = RawBody::from_opts(opts, &items, has_data)?;
+        let method = match method {
+            // 方法名不区分大小写，PURGE / PROPFIND 这样的自定义方法也可以使用
+            Some(m) => Method::from_bytes(m.to_ascii_uppercase().as_bytes())?,
+            None if has_data || raw_body.is_some() => Method::POST,
+            None => Method::GET,
+        };
+        // 和 HTTPie 一样，上传文件需要显式地使用 --form 或者 --multipart
+        let has_file = items.iter().any(|i| matches!(i, RequestItem::File(..)));
+        let body_kind = match (opts.form, opts.multipart, has_file) {
+            (_, true, _) | (true, _, true) => BodyKind::Multipart,
+            (true, _, false) => BodyKind::Form,
+            (false, false, true) => {
+                return Err(anyhow!("file fields (field@path) require --form or --multipart"))
+            }
+            (false, false, false) => BodyKind::Json,
+        };
         Ok(Self {
-            // 从迭代器中取第一个结果作为key，迭代器返回Some(T)/None
-            // 我们将其转换成Ok(T)/Err(E)，然后用?处理错误
-            k: (split.next().ok_or_else(err)?).to_string(),
-            // 从迭代器中取第二个结果作为value
-            v: (split.next().ok_or_else(err)?).to_string(),
+            method,
+            url,
+            items,
+            body_kind,
+            raw_body,
         })
     }
 }
 
-/// 因为我们为 KvPair 实现了 FromStr，这里可以直接 s.parse() 得到 KvPair
-fn parse_kv_pair(s: &str) -> Result<KvPair> {
-    Ok(s.parse()?)
-}
+impl RawBody {
+    /// 依次从 --raw、@file 和管道输入的 stdin 中获取原始 body，它们不能和 body 字段同时使用
+    fn from_opts(opts: &Opts, items: &[RequestItem], has_data: bool) -> Result<Option<Self>> {
+        let mut sources = Vec::new();
+        if let Some(raw) = &opts.raw {
+            sources.push(Self {
+                data: raw.clone().into_bytes(),
+                mime: None,
+            });
+        }
+        for item in items {
+            if let RequestItem::BodyFile(path) = item {
+                let data = fs::read(path)
+                    .with_context(|| format!("Failed to read {}", path.display()))?;
+                sources.push(Self {
+                    data,
+                    mime: items::guess_mime(path),
+                });
+            }
+        }
+        if sources.len() > 1 {
+            return Err(anyhow!("only one of --raw and @file can be used as the request body"));
+        }
+        if !sources.is_empty() && has_data {
+            return Err(anyhow!(
+                "request body (from --raw or @file) and request data (key=value) cannot be mixed"
+            ));
+        }
+        // stdin 只在没有其它 body 时才会被读取，这样 `echo ... | httpie url a=1` 不会因为 stdin 而报错
+        if sources.is_empty() && !has_data && !opts.ignore_stdin {
+            sources.extend(Self::from_stdin()?);
+        }
+        Ok(sources.pop().map(|mut body| {
+            if body.mime.is_none() && opts.json {
+                body.mime = Some(mime::APPLICATION_JSON);
+            } else if body.mime.is_none() && opts.form {
+                body.mime = Some(mime::APPLICATION_WWW_FORM_URLENCODED);
+            }
+            body
+        }))
+    }
 
-fn parse_url(s: &str) -> Result<String> {
-    // 这里我们仅仅检查一下url是否合法
-    let _url: Url = s.parse()?;
-    Ok(s.into())
-}
+    fn from_stdin() -> Result<Option<Self>> {
+        let mut stdin = io::stdin();
+        // stdin 是终端说明没有管道输入
+        if stdin.is_terminal() {
+            return Ok(None);
+        }
+        let mut data = Vec::new();
+        stdin.read_to_end(&mut data)?;
+        Ok((!data.is_empty()).then_some(Self { data, mime: None }))
+    }
+}
 
-fn print_syntect(body: &str, ext: &str) {
-    let ps = SyntaxSet::load_defaults_newlines();
-    let ts = ThemeSet::load_defaults();
+// 标准的 HTTP 方法，不区分大小写
+const METHODS: [&str; 9] = ["GET", "HEAD", "POST", "PUT", "DELETE", "PATCH", "OPTIONS", "TRACE", "CONNECT"];
 
-    let syntax = ps.find_syntax_by_extension(ext).unwrap();
-    let mut h = HighlightLines::new(syntax, &ts.themes["base16-ocean.dark"]);
-    for line in LinesWithEndings::from(body) {
-        let ranges: Vec<(Style, &str)> = h.highlight(line, &ps);
-        let escaped = as_24_bit_terminal_escaped(&ranges[..], true);
-        print!("{}", escaped);
+/// 判断第一个参数是不是 HTTP 方法，它必须由纯字母组成。标准的方法和全大写的单词（如 PURGE）
+/// 总是被当作方法，因此 `httpie DELETE myservice:8080/items/1` 中的第二个参数是 URL。
+/// 其它小写的单词只有在紧随其后的是 URL 而不是请求项时才是方法，
+/// 这样 `httpie localhost a=1` 中的 localhost 会被当成 URL
+fn is_method(first: &str, second: &str) -> bool {
+    if first.is_empty() || !first.chars().all(|c| c.is_ascii_alphabetic()) {
+        return false;
     }
+    METHODS.iter().any(|m| m.eq_ignore_ascii_case(first))
+        || first.chars().all(|c| c.is_ascii_uppercase())
+        || looks_like_url(second)
+        || !items::is_item(second)
 }
 
-fn print_status(resp: &Response) {
-    if resp.status().is_client_error() {
-        println!("{}", resp.status().to_string().red());
-    } else if resp.status().is_server_error() {
-        println!("{}", resp.status().to_string().red());
-    } else {
-        let status = format!("{:?} {}", resp.version(), resp.status()).green();
-        println!("{}\n", status);
+/// `localhost:8080/api`、`example.com/a?b=1` 这样的 URL 同时也是合法的 Header:Value 或者 field=value，
+/// 这里根据开头是否像一个主机名（localhost 或者带点的域名）、后面是否紧跟端口或路径来区分
+fn looks_like_url(s: &str) -> bool {
+    if s.contains("://") || s.starts_with(':') || s.starts_with('/') {
+        return true;
     }
+    let end = s.find([':', '/', '?', '#']).unwrap_or(s.len());
+    let (host, rest) = s.split_at(end);
+    let is_host = !host.is_empty()
+        && host.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '.')
+        && (host == "localhost" || host.contains('.'));
+    // `user.name:=1` 这样的 JSON 字段冒号后面不是端口
+    is_host
+        && match rest.strip_prefix(':') {
+            Some(port) => port.starts_with(|c: char| c.is_ascii_digit() || c == '/'),
+            None => true,
+        }
 }
 
-fn print_headers(resp: &Response) {
-    for (name, value) in resp.headers().iter() {
-        println!("{}: {}", name.to_string().green(), value.to_str().unwrap());
-    }
-    print!("\n");
+/// 解析 URL，和 HTTPie 一样支持一些简写：
+///   :3000/path      http://localhost:3000/path
+///   :/path          http://localhost/path
+///   example.com     http://example.com
+fn parse_url(s: &str, default_scheme: &str) -> Result<Url> {
+    let is_port_or_path =
+        |rest: &str| rest.is_empty() || rest.starts_with(|c: char| c.is_ascii_digit() || c == '/');
+    let url = match s.strip_prefix(':') {
+        // `:` 后面紧跟着端口号或者路径时，主机是 localhost
+        Some(rest) if is_port_or_path(rest) => format!("{}://localhost:{}", default_scheme, rest),
+        _ if has_scheme(s) => s.to_string(),
+        _ => format!("{}://{}", default_scheme, s),
+    };
+    // `:/path` 展开之后是 `localhost:/path`，端口为空，Url 会把它当成没有端口
+    url.parse().with_context(|| format!("Invalid URL: {}", s))
 }
 
-/// 打印服务器返回的 HTTP body
-fn print_body(m: Option<Mime>, body: &str) {
-    match m {
-        // 对于 "application/json" 我们 pretty print
-        Some(v) if v == mime::APPLICATION_JSON => print_syntect(body, "json"),
-        // 对于 "application/xml" 我们格式化输出
-        Some(v) if v == mime::TEXT_HTML => print_syntect(body, "html"),
-        // 其它情况，直接输出
-        _ => println!("{}", body.cyan()),
+/// URL 是否以 `scheme://` 开头，scheme 由字母开头，只能包含字母、数字和 `+-.`
+fn has_scheme(s: &str) -> bool {
+    match s.split_once("://") {
+        Some((scheme, _)) => {
+            scheme.starts_with(|c: char| c.is_ascii_alphabetic())
+                && scheme
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || "+-.".contains(c))
+        }
+        None => false,
     }
 }
 
-async fn print_resp(resp: Response) -> Result<()> {
-    print_status(&resp);
-    print_headers(&resp);
-    let mime = get_content_type(&resp);
-    let body = resp.text().await?;
-    print_body(mime, &body);
-    Ok(())
+/// 把 param==value 追加到 URL 的 query 中，URL 中原有的 query 保持不变，
+/// 同名的参数会重复出现，如 `a==1 a==2` 得到 `?a=1&a=2`
+fn append_query(url: &mut Url, items: &[RequestItem]) {
+    let params: Vec<_> = items
+        .iter()
+        .filter_map(|item| match item {
+            RequestItem::Query(name, value) => Some((name, value)),
+            _ => None,
+        })
+        .collect();
+    // 没有参数时不调用 query_pairs_mut，否则会在 URL 末尾留下一个多余的 ?
+    if !params.is_empty() {
+        url.query_pairs_mut().extend_pairs(params);
+    }
 }
 
-fn get_content_type(resp: &Response) -> Option<Mime> {
-    resp.headers()
-        .get(header::CONTENT_TYPE)
-        .map(|v| v.to_str().unwrap().parse().unwrap())
-}
+// 发送 JSON 时和 HTTPie 一样优先接收 JSON 格式的响应
+const JSON_ACCEPT: &str = "application/json, */*;q=0.5";
 
-async fn get(client: Client, args: &Get) -> Result<()> {
-    let resp = client.get(&args.url).send().await?;
-    // 打印状态码
-    // println!("{:?}", resp.status());
-    // 打印返回的头部
-    // println!("{:#?}", resp.headers());
-    // 读取并打印返回的body
-    // let body = resp.text().await?;
-    // println!("{}", body);
-    Ok(print_resp(resp).await?)
+/// 为我们的http客户端增加一些缺省的头部
+fn default_headers() -> Result<header::HeaderMap> {
+    let mut headers = header::HeaderMap::new();
+    // headers.insert("X-POWERED-BY", header::HeaderValue::from_static("Rust"));
+    headers.insert("X-POWERED-BY", "Rust".parse()?);
+    headers.insert(header::USER_AGENT, "Rust Httpie".parse()?);
+    // reqwest 的客户端本身也会加上 Accept: */*，这里显式地写出来，--offline 打印时才能看到它
+    headers.insert(header::ACCEPT, "*/*".parse()?);
+    Ok(headers)
 }
 
-async fn post(client: Client, args: &Post) -> Result<()> {
-    let mut body = HashMap::new();
-    for pair in args.body.iter() {
-        body.insert(&pair.k, &pair.v);
+/// 根据命令行参数构造请求，所有的 HTTP 方法共用这段逻辑
+async fn build_request(client: &Client, args: &RequestArgs) -> Result<Request> {
+    let mut req = client.request(args.method.clone(), args.url.clone());
+    // query 参数已经合并到 URL 中了，这里把请求头单独收集起来，body 字段则单独组装
+    let mut headers = header::HeaderMap::new();
+    for item in args.items.iter() {
+        if let Some((name, value)) = item.header()? {
+            headers.append(name, value);
+        }
     }
-    let resp = client.post(&args.url).json(&body).send().await?;
-    // println!("{:?}", resp.status());
-    // println!("{:#?}", resp.headers());
-    // let body = resp.text().await?;
-    // println!("{}", body);
-    Ok(print_resp(resp).await?)
-    // let mut body = serde_json::Map::new();
-    // for kv in args.body.iter() {
-    //     body.insert(kv.k.clone(), serde_json::json!(kv.v));
-    // }
-    // let resp = client
-    //     .post(&args.url)
-    //     .header(header::CONTENT_TYPE, "application/json")
-    //     .body(serde_json::Value::Object(body).to_string())
-    //     .send()
-    //     .await?;
-    // // 打印状态码
-    // println!("{:?}", resp.status());
-    // // 打印返回的头部
-    // println!("{:#?}", resp.headers());
-    // // 读取并打印返回的body
-    // let body = resp.text().await?;
-    // println!("{}", body);
-    // Ok(())
+    match (&args.raw_body, args.body_kind) {
+        (Some(raw), _) => {
+            if let Some(mime) = &raw.mime {
+                req = req.header(header::CONTENT_TYPE, mime.as_ref());
+            }
+            req = req.body(raw.data.clone());
+        }
+        // 没有 body 字段时不发送 body，这样 GET / HEAD / DELETE 不会带上一个空的 {}
+        (None, BodyKind::Json) => {
+            if let Some(body) = items::json_body(&args.items)? {
+                req = req
+                    .header(header::ACCEPT, JSON_ACCEPT)
+                    .header(header::CONTENT_TYPE, mime::APPLICATION_JSON.as_ref())
+                    .body(body.to_string());
+            }
+        }
+        (None, BodyKind::Form) => {
+            let form = items::form_body(&args.items)?;
+            if !form.is_empty() {
+                // form() 会同时设置 application/x-www-form-urlencoded 的 Content-Type
+                req = req.form(&form);
+            }
+        }
+        // 和上面一样，没有 body 字段时不发送只有结束 boundary 的空 multipart body
+        (None, BodyKind::Multipart) if !args.items.iter().any(RequestItem::is_data) => {}
+        // multipart() 会设置带 boundary 的 Content-Type
+        (None, BodyKind::Multipart) => match items::multipart_text_body(&args.items)? {
+            Some((content_type, body)) => req = req.header(header::CONTENT_TYPE, content_type).body(body),
+            None => req = req.multipart(items::multipart_body(&args.items).await?),
+        },
+    }
+    // 用户指定的请求头最后设置，这样可以覆盖上面自动设置的 Content-Type 等
+    req = req.headers(headers);
+    let mut req = req.build()?;
+    // 客户端的缺省头部要到发送时才会加上，这里提前合并进来，这样打印出来的请求和实际发送的一致
+    for (name, value) in default_headers()?.iter() {
+        if !req.headers().contains_key(name) {
+            req.headers_mut().insert(name, value.clone());
+        }
+    }
+    Ok(req)
 }
 
 #[tokio::main]
 async fn main() -> Result<()> {
     let opts: Opts = Opts::parse();
-    let mut headers = header::HeaderMap::new();
-    // 为我们的http客户端增加一些缺省的头部
-    // headers.insert("X-POWERED-BY", header::HeaderValue::from_static("Rust"));
-    headers.insert("X-POWERED-BY", "Rust".parse()?);
-    headers.insert(header::USER_AGENT, "Rust Httpie".parse()?);
+    match run(opts).await {
+        // 输出被 `| head` 这样提前关闭时，和 cat 一样安静地退出，而不是报错
+        Err(e) if is_broken_pipe(&e) => Ok(()),
+        result => result,
+    }
+}
+
+fn is_broken_pipe(e: &anyhow::Error) -> bool {
+    e.chain()
+        .filter_map(|e| e.downcast_ref::<io::Error>())
+        .any(|e| e.kind() == io::ErrorKind::BrokenPipe)
+}
+
+async fn run(opts: Opts) -> Result<()> {
+    if opts.list_styles {
+        return theme::list_styles();
+    }
     // 生成一个 HTTP 客户端
     let client = Client::builder()
-        .default_headers(headers)
+        .default_headers(default_headers()?)
         .build()?;
-    let result = match opts.subcmd {
-        SubCommand::Get(ref args) => get(client, args).await?,
-        SubCommand::Post(ref args) => post(client, args).await?,
-    };
-    Ok(result)
+    let args = RequestArgs::try_from(&opts)?;
+    let mut req = build_request(&client, &args).await?;
+    let downloaded = download::prepare(&mut req, opts.output.as_deref(), opts.resume).await?;
+    let print = opts.print_options()?;
+    output::print_request(&req, &print)?;
+    // --offline 时只打印请求，不会有任何网络 I/O
+    if opts.offline {
+        return Ok(());
+    }
+    let started = Instant::now();
+    let resp = client.execute(req).await?;
+    if opts.download {
+        output::print_resp_headers(&resp, &print)?;
+        return download::download(resp, opts.output.as_deref(), downloaded).await;
+    }
+    output::print_resp(resp, &args.method, &print, started, opts.stream).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn url(s: &str) -> String {
+        parse_url(s, "http").unwrap().to_string()
+    }
+
+    #[test]
+    fn url_shorthands() {
+        assert_eq!(url(":3000/health"), "http://localhost:3000/health");
+        assert_eq!(url(":3000"), "http://localhost:3000/");
+        assert_eq!(url(":/path"), "http://localhost/path");
+        assert_eq!(url(":"), "http://localhost/");
+        assert_eq!(url("localhost:8080/api"), "http://localhost:8080/api");
+        assert_eq!(url("example.com"), "http://example.com/");
+        assert_eq!(url("https://example.com/a?b=c"), "https://example.com/a?b=c");
+        assert_eq!(url("ws+unix://x"), "ws+unix://x");
+    }
+
+    #[test]
+    fn default_scheme_is_used_only_without_a_scheme() {
+        assert_eq!(parse_url("example.com", "https").unwrap().as_str(), "https://example.com/");
+        assert_eq!(parse_url(":8443", "https").unwrap().as_str(), "https://localhost:8443/");
+        assert_eq!(parse_url("http://example.com", "https").unwrap().as_str(), "http://example.com/");
+        // 查询参数中的 :// 不是 scheme
+        assert_eq!(url("example.com/?next=http://x"), "http://example.com/?next=http://x");
+    }
+
+    #[test]
+    fn invalid_urls() {
+        assert!(parse_url("http://", "http").is_err());
+        assert!(parse_url(":99999", "http").is_err());
+    }
+
+    #[test]
+    fn standard_and_uppercase_methods() {
+        assert!(is_method("DELETE", "myservice:8080/items/1"));
+        assert!(is_method("delete", "myservice:8080/items/1"));
+        assert!(is_method("PURGE", "myservice:8080/cache"));
+        assert!(is_method("get", "example.com"));
+        assert!(!is_method("GET1", "example.com"));
+        assert!(!is_method("", "example.com"));
+    }
+
+    #[test]
+    fn lowercase_words_are_guessed() {
+        assert!(is_method("purge", "example.com"));
+        assert!(is_method("purge", ":3000/cache"));
+        assert!(!is_method("localhost", "a=1"));
+        assert!(!is_method("myservice", "X-Token:abc"));
+        assert!(is_method("purge", "example.com/cache?key=1"));
+        assert!(is_method("purge", "localhost/cache?key=1"));
+        assert!(!is_method("example.com", "user.name=x"));
+        assert!(!is_method("example.com", "user.age:=1"));
+    }
+
+    #[test]
+    fn urls_that_look_like_headers() {
+        assert!(looks_like_url("localhost:8080/api"));
+        assert!(looks_like_url("api.example.com:443"));
+        assert!(looks_like_url(":3000"));
+        assert!(!looks_like_url("X-Token:abc"));
+    }
 }
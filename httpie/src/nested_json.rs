@@ -0,0 +1,301 @@
+use anyhow::{anyhow, Result};
+use serde_json::{Map, Value};
+
+// 和 HTTPie 一样支持在 body 字段的 key 中用方括号描述嵌套的 JSON 结构：
+//   user[name]=x        {"user": {"name": "x"}}
+//   user[roles][]=a     {"user": {"roles": ["a"]}}
+//   items[1][id]:=3     {"items": [null, {"id": 3}]}
+//   []=a                ["a"]，顶层也可以是数组
+
+// 下标最多只能比数组当前的长度大这么多，避免 `a[100000000000]` 这样的下标分配出巨大的数组
+const MAX_INDEX_GAP: usize = 1024;
+
+/// key 路径中的一段
+#[derive(Debug, Clone, PartialEq)]
+enum Segment {
+    /// `name` 或 `[name]`，访问对象的字段
+    Key(String),
+    /// `[0]`，访问数组的某个下标
+    Index(usize),
+    /// `[]`，在数组末尾追加
+    Append,
+}
+
+impl Segment {
+    fn kind(&self) -> &'static str {
+        match self {
+            Segment::Key(_) => "object",
+            Segment::Index(_) | Segment::Append => "array",
+        }
+    }
+}
+
+/// 解析出来的一段路径，以及它在原始 key 中的位置，用于报错时指出出错的部分
+#[derive(Debug, Clone, PartialEq)]
+struct Spanned {
+    segment: Segment,
+    start: usize,
+    end: usize,
+}
+
+fn parse_path(path: &str) -> Result<Vec<Spanned>> {
+    let mut segments = Vec::new();
+    // 第一段是方括号之前的部分，它为空时表示顶层直接用方括号访问
+    let first_end = path.find('[').unwrap_or(path.len());
+    if first_end > 0 {
+        segments.push(Spanned {
+            segment: Segment::Key(path[..first_end].to_string()),
+            start: 0,
+            end: first_end,
+        });
+    }
+
+    let mut pos = first_end;
+    while pos < path.len() {
+        if !path[pos..].starts_with('[') {
+            return Err(syntax_error(path, pos, 1, "expected `[`"));
+        }
+        let close = path[pos..]
+            .find(']')
+            .map(|i| pos + i)
+            .ok_or_else(|| syntax_error(path, pos, path.len() - pos, "unclosed `[`"))?;
+        let inner = &path[pos + 1..close];
+        let segment = if inner.is_empty() {
+            Segment::Append
+        } else if inner.bytes().all(|b| b.is_ascii_digit()) {
+            let index = inner
+                .parse()
+                .map_err(|_| syntax_error(path, pos, close + 1 - pos, "array index is too large"))?;
+            Segment::Index(index)
+        } else {
+            Segment::Key(inner.to_string())
+        };
+        segments.push(Spanned {
+            segment,
+            start: pos,
+            end: close + 1,
+        });
+        pos = close + 1;
+    }
+    Ok(segments)
+}
+
+/// 按照 key 中描述的路径把 value 放到 root 中。root 一开始可以是 null，它会根据第一段路径
+/// 变成对象或数组。同一个 key 出现多次时，值会被合并成数组
+pub fn insert(root: &mut Value, path: &str, value: Value) -> Result<()> {
+    let segments = parse_path(path)?;
+    let mut cur = root;
+    for (i, spanned) in segments.iter().enumerate() {
+        let last = i + 1 == segments.len();
+        // null 说明这一层还没有被创建过，按照当前这段路径的类型创建它
+        if cur.is_null() {
+            *cur = match spanned.segment {
+                Segment::Key(_) => Value::Object(Map::new()),
+                _ => Value::Array(Vec::new()),
+            };
+        }
+        let found = type_name(cur);
+        cur = match (&spanned.segment, cur) {
+            (Segment::Key(k), Value::Object(obj)) => {
+                if last {
+                    match obj.get_mut(k) {
+                        Some(existing) => append(existing, value),
+                        None => {
+                            obj.insert(k.clone(), value);
+                        }
+                    }
+                    return Ok(());
+                }
+                obj.entry(k.clone()).or_insert(Value::Null)
+            }
+            (Segment::Index(n), Value::Array(arr)) => {
+                let too_large = arr.len().checked_add(MAX_INDEX_GAP).is_none_or(|max| *n > max);
+                if too_large {
+                    let msg = format!(
+                        "array index {} is too large, the array has {} items",
+                        n,
+                        arr.len()
+                    );
+                    return Err(syntax_error(path, spanned.start, spanned.end - spanned.start, &msg));
+                }
+                if arr.len() <= *n {
+                    arr.resize(n + 1, Value::Null);
+                }
+                if last {
+                    arr[*n] = value;
+                    return Ok(());
+                }
+                &mut arr[*n]
+            }
+            (Segment::Append, Value::Array(arr)) => {
+                if last {
+                    arr.push(value);
+                    return Ok(());
+                }
+                arr.push(Value::Null);
+                arr.last_mut().unwrap()
+            }
+            (segment, _) => {
+                let msg = format!(
+                    "cannot use {} as an {}, it is already {}",
+                    describe(path, &segments[..i]),
+                    segment.kind(),
+                    found
+                );
+                return Err(syntax_error(path, spanned.start, spanned.end - spanned.start, &msg));
+            }
+        };
+    }
+    Ok(())
+}
+
+/// 同一个 key 被赋值多次时，把已有的值变成数组，再把新值追加进去
+fn append(existing: &mut Value, value: Value) {
+    match existing {
+        Value::Array(arr) => arr.push(value),
+        _ => *existing = Value::Array(vec![existing.take(), value]),
+    }
+}
+
+fn type_name(v: &Value) -> &'static str {
+    match v {
+        Value::Null => "null",
+        Value::Bool(_) => "a boolean",
+        Value::Number(_) => "a number",
+        Value::String(_) => "a string",
+        Value::Array(_) => "an array",
+        Value::Object(_) => "an object",
+    }
+}
+
+/// 用于报错的路径描述，顶层没有名字时称为 body
+fn describe(path: &str, parents: &[Spanned]) -> String {
+    match parents.last() {
+        Some(s) => format!("`{}`", &path[..s.end]),
+        None => "the body".to_string(),
+    }
+}
+
+/// 生成一个带有指示位置的错误信息，例如：
+///
+/// ```text
+/// cannot use `a` as an array, it is already an object
+///   a[]
+///    ^^
+/// ```
+pub fn syntax_error(input: &str, pos: usize, len: usize, msg: &str) -> anyhow::Error {
+    let prefix = input[..pos].chars().count();
+    let width = input[pos..pos + len].chars().count().max(1);
+    anyhow!(
+        "{}\n  {}\n  {}{}",
+        msg,
+        input,
+        " ".repeat(prefix),
+        "^".repeat(width)
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn build(items: &[(&str, Value)]) -> Result<Value> {
+        let mut root = Value::Null;
+        for (path, value) in items {
+            insert(&mut root, path, value.clone())?;
+        }
+        Ok(root)
+    }
+
+    fn error(items: &[(&str, Value)]) -> String {
+        build(items).unwrap_err().to_string()
+    }
+
+    #[test]
+    fn flat_keys() {
+        let body = build(&[("a", json!("x")), ("b", json!(1))]).unwrap();
+        assert_eq!(body, json!({"a": "x", "b": 1}));
+    }
+
+    #[test]
+    fn nested_objects_and_arrays() {
+        let body = build(&[
+            ("user[name]", json!("x")),
+            ("user[roles][]", json!("admin")),
+            ("user[roles][]", json!("dev")),
+            ("items[1][id]", json!(3)),
+        ])
+        .unwrap();
+        assert_eq!(
+            body,
+            json!({
+                "user": {"name": "x", "roles": ["admin", "dev"]},
+                "items": [null, {"id": 3}]
+            })
+        );
+    }
+
+    #[test]
+    fn top_level_array() {
+        let body = build(&[("[]", json!("a")), ("[2]", json!("c"))]).unwrap();
+        assert_eq!(body, json!(["a", null, "c"]));
+    }
+
+    #[test]
+    fn repeated_key_becomes_array() {
+        let body = build(&[("a", json!(1)), ("a", json!(2)), ("a", json!(3))]).unwrap();
+        assert_eq!(body, json!({"a": [1, 2, 3]}));
+    }
+
+    #[test]
+    fn index_overwrites_existing_item() {
+        let body = build(&[("a[0]", json!(1)), ("a[0]", json!(2))]).unwrap();
+        assert_eq!(body, json!({"a": [2]}));
+    }
+
+    #[test]
+    fn type_conflicts_point_at_the_segment() {
+        assert_eq!(
+            error(&[("a[b]", json!(1)), ("a[]", json!(2))]),
+            "cannot use `a` as an array, it is already an object\n  a[]\n   ^^"
+        );
+        assert_eq!(
+            error(&[("a", json!("x")), ("a[b]", json!(1))]),
+            "cannot use `a` as an object, it is already a string\n  a[b]\n   ^^^"
+        );
+        assert_eq!(
+            error(&[("[]", json!(1)), ("a", json!(2))]),
+            "cannot use the body as an object, it is already an array\n  a\n  ^"
+        );
+    }
+
+    #[test]
+    fn malformed_paths() {
+        assert_eq!(error(&[("a[b", json!(1))]), "unclosed `[`\n  a[b\n   ^^");
+        assert_eq!(error(&[("a[b]c", json!(1))]), "expected `[`\n  a[b]c\n      ^");
+    }
+
+    #[test]
+    fn huge_indexes_are_rejected() {
+        assert_eq!(
+            error(&[("a[18446744073709551616]", json!(1))]),
+            "array index is too large\n  a[18446744073709551616]\n   ^^^^^^^^^^^^^^^^^^^^^^"
+        );
+        assert_eq!(
+            error(&[("a[18446744073709551615]", json!(1))]),
+            "array index 18446744073709551615 is too large, the array has 0 items\n  \
+             a[18446744073709551615]\n   ^^^^^^^^^^^^^^^^^^^^^^"
+        );
+        assert!(error(&[("a[100000000000]", json!(1))]).starts_with("array index 100000000000 is too large"));
+        assert!(build(&[("a[1024]", json!(1))]).is_ok());
+    }
+
+    #[test]
+    fn caret_counts_characters() {
+        assert_eq!(
+            error(&[("名字", json!(1)), ("名字[]", json!(2))]),
+            "cannot use `名字` as an array, it is already a number\n  名字[]\n    ^^"
+        );
+    }
+}
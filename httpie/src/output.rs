@@ -0,0 +1,508 @@
+use std::{
+    borrow::Cow,
+    io::{self, Write},
+    str::FromStr,
+    sync::OnceLock,
+    time::Instant,
+};
+use anyhow::{anyhow, Result};
+use clap::ValueEnum;
+use colored::Colorize;
+use encoding_rs::{Encoding, UTF_16BE, UTF_16LE};
+use futures_util::StreamExt;
+use mime::Mime;
+use reqwest::{header::{self, HeaderMap, HeaderValue}, Method, Request, Response, Url};
+use crate::{
+    charset,
+    formatters::{self, FormatOptions},
+    terminal::{self, ColorDepth},
+    theme::Palette,
+};
+use syntect::{
+    easy::HighlightLines,
+    highlighting::{Color, Style, Theme},
+    parsing::SyntaxSet,
+    util::LinesWithEndings,
+};
+
+/// --pretty，分别控制是否使用颜色和是否重新排版 body
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Pretty {
+    /// 使用颜色并重新排版
+    All,
+    /// 只使用颜色
+    Colors,
+    /// 只重新排版
+    Format,
+    /// 原样输出
+    None,
+}
+
+impl Pretty {
+    pub fn colors(self) -> bool {
+        matches!(self, Self::All | Self::Colors)
+    }
+
+    pub fn format(self) -> bool {
+        matches!(self, Self::All | Self::Format)
+    }
+}
+
+/// 要打印请求和响应中的哪些部分，对应 --print 中的 HBhbm
+#[derive(Debug, Clone, Default)]
+pub struct PrintOptions {
+    request_headers: bool,
+    request_body: bool,
+    response_headers: bool,
+    response_body: bool,
+    meta: bool,
+    /// 是否直接输出二进制的 body，否则只打印一个提示
+    binary: bool,
+    /// 用来代替响应中 Content-Type 的编码和类型
+    response_charset: Option<&'static Encoding>,
+    response_mime: Option<Mime>,
+    format: FormatOptions,
+    /// 是否按照 format 重新排版 body
+    reformat: bool,
+    /// 终端的颜色种类，None 表示不使用颜色
+    colors: Option<ColorDepth>,
+    /// 超过这个大小的 body 只排版不高亮，逐行高亮几 MB 的 body 需要好几秒
+    highlight_limit: usize,
+    /// 高亮 body 的主题，状态行和头部的颜色也来自它
+    theme: Theme,
+    palette: Palette,
+}
+
+impl FromStr for PrintOptions {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut opts = Self::default();
+        for c in s.chars() {
+            match c {
+                'H' => opts.request_headers = true,
+                'B' => opts.request_body = true,
+                'h' => opts.response_headers = true,
+                'b' => opts.response_body = true,
+                'm' => opts.meta = true,
+                _ => return Err(anyhow!("invalid --print value `{}`, expected any of HBhbm", c)),
+            }
+        }
+        Ok(opts)
+    }
+}
+
+impl PrintOptions {
+    fn request(&self) -> bool {
+        self.request_headers || self.request_body
+    }
+
+    fn response(&self) -> bool {
+        self.response_headers || self.response_body || self.meta
+    }
+
+    /// 输出被重定向或者指定了 --binary 时，二进制的 body 会被原样输出
+    pub fn with_binary(self, binary: bool) -> Self {
+        Self { binary, ..self }
+    }
+
+    /// 请求和响应的 body 以及头部都按照这些选项来排版
+    pub fn with_format(self, format: FormatOptions) -> Self {
+        Self { format, ..self }
+    }
+
+    /// 按照终端支持的颜色种类给状态行、头部上色，不使用颜色时原样返回
+    fn paint(&self, text: &str, color: impl Fn(&Palette) -> Color) -> String {
+        match self.colors {
+            Some(depth) => terminal::paint(text, color(&self.palette), depth),
+            None => text.to_string(),
+        }
+    }
+
+    /// --pretty 等决定的是否使用颜色以及是否重新排版
+    pub fn with_pretty(self, colors: Option<ColorDepth>, reformat: bool) -> Self {
+        Self {
+            colors,
+            reformat,
+            ..self
+        }
+    }
+
+    /// 设置 --max-highlight-size
+    pub fn with_highlight_limit(self, highlight_limit: usize) -> Self {
+        Self {
+            highlight_limit,
+            ..self
+        }
+    }
+
+    /// 使用 --style 选择的主题
+    pub fn with_theme(self, theme: Theme) -> Self {
+        let palette = Palette::from_theme(&theme);
+        Self {
+            theme,
+            palette,
+            ..self
+        }
+    }
+
+    /// 服务器返回的 Content-Type 不正确时，用 --response-charset 和 --response-mime 来纠正
+    pub fn with_response_overrides(
+        self,
+        response_charset: Option<&'static Encoding>,
+        response_mime: Option<Mime>,
+    ) -> Self {
+        Self {
+            response_charset,
+            response_mime,
+            ..self
+        }
+    }
+}
+
+/// 高亮用的语法定义，来自 syntect 中预先编译好的 dump，只在第一次高亮时加载一次
+fn syntax_set() -> &'static SyntaxSet {
+    static SYNTAXES: OnceLock<SyntaxSet> = OnceLock::new();
+    SYNTAXES.get_or_init(SyntaxSet::load_defaults_newlines)
+}
+
+fn print_syntect(out: &mut impl Write, body: &str, ext: &str, theme: &Theme, depth: ColorDepth) -> io::Result<()> {
+    let ps = syntax_set();
+    // 找不到对应的语法时按纯文本输出
+    let Some(syntax) = ps.find_syntax_by_extension(ext) else {
+        return print_plain(out, body);
+    };
+    let mut h = HighlightLines::new(syntax, theme);
+    for line in LinesWithEndings::from(body) {
+        let ranges: Vec<(Style, &str)> = h.highlight(line, ps);
+        let escaped = terminal::escaped(&ranges[..], depth);
+        write!(out, "{}", escaped)?;
+    }
+    // 恢复终端的颜色，并保证输出以换行结束
+    write!(out, "\x1b[0m")?;
+    if !body.ends_with('\n') {
+        writeln!(out)?;
+    }
+    Ok(())
+}
+
+fn print_plain(out: &mut impl Write, body: &str) -> io::Result<()> {
+    write!(out, "{}", body.cyan())?;
+    if !body.ends_with('\n') {
+        writeln!(out)?;
+    }
+    Ok(())
+}
+
+fn print_status(out: &mut impl Write, resp: &Response, opts: &PrintOptions) -> io::Result<()> {
+    if resp.status().is_client_error() || resp.status().is_server_error() {
+        writeln!(out, "{}", opts.paint(&resp.status().to_string(), |p| p.error))
+    } else {
+        let status = opts.paint(&format!("{:?} {}", resp.version(), resp.status()), |p| p.ok);
+        writeln!(out, "{}\n", status)
+    }
+}
+
+fn print_headers(out: &mut impl Write, headers: &HeaderMap, opts: &PrintOptions) -> io::Result<()> {
+    let mut headers: Vec<_> = headers.iter().collect();
+    // 排序是稳定的，同名的头部之间保持原来的顺序
+    if opts.format.headers_sort {
+        headers.sort_by_key(|(name, _)| name.as_str());
+    }
+    for (name, value) in headers {
+        writeln!(out, "{}: {}", opts.paint(name.as_str(), |p| p.name), header_value(value))?;
+    }
+    writeln!(out)
+}
+
+/// 头部的值中可能有服务器发来的非 ASCII 字节，这时把它们转义之后再输出
+fn header_value(value: &HeaderValue) -> Cow<'_, str> {
+    match value.to_str() {
+        Ok(s) => Cow::Borrowed(s),
+        Err(_) => Cow::Owned(value.as_bytes().escape_ascii().to_string()),
+    }
+}
+
+/// 打印 HTTP body，请求和响应的 body 都用它来打印
+fn print_body(out: &mut impl Write, m: Option<Mime>, body: &str, opts: &PrintOptions) -> io::Result<()> {
+    // 由 MIME 类型对应的格式化器决定如何排版和高亮，见 formatters
+    let formatter = formatters::registry().find(m.as_ref());
+    let body = if opts.reformat {
+        formatter.format(body, &opts.format)
+    } else {
+        body.to_string()
+    };
+    match (formatter.syntax(), opts.colors) {
+        (Some(ext), Some(depth)) if body.len() <= opts.highlight_limit => {
+            print_syntect(out, &body, ext, &opts.theme, depth)
+        }
+        // 没有对应语法、不使用颜色或者太大的，直接输出
+        _ => print_plain(out, &body),
+    }
+}
+
+/// 打印还没有解码的 body，二进制的数据在终端中只显示一个提示，除非允许原样输出。
+/// 文本数据按照 charset 或者 Content-Type 中的编码解码，见 charset::detect，无法解码的字节会被替换成 U+FFFD
+fn print_body_bytes(
+    out: &mut impl Write,
+    m: Option<Mime>,
+    body: &[u8],
+    opts: &PrintOptions,
+    charset: Option<&'static Encoding>,
+) -> io::Result<()> {
+    let encoding = charset::detect(body, m.as_ref(), charset);
+    if !is_binary(m.as_ref(), body, encoding) {
+        let (text, _, _) = encoding.decode(body);
+        print_body(out, m, &text, opts)
+    } else if opts.binary {
+        out.write_all(body)
+    } else {
+        print_binary_note(out, m.as_ref(), Some(body.len() as u64))
+    }
+}
+
+fn print_binary_note(out: &mut impl Write, m: Option<&Mime>, len: Option<u64>) -> io::Result<()> {
+    let size = match len {
+        Some(len) => indicatif::HumanBytes(len).to_string(),
+        None => "unknown size".to_string(),
+    };
+    let note = format!(
+        "+-----------------------------------------+\n\
+         | NOTE: binary data not shown in terminal |\n\
+         +-----------------------------------------+\n\
+         binary data, {}, {}",
+        size,
+        m.map(|m| m.essence_str()).unwrap_or("unknown type")
+    );
+    writeln!(out, "{}", note.cyan())
+}
+
+/// 判断 body 是不是二进制数据：先看 MIME 类型，文本类型的数据中不会有 NUL 字节；
+/// 类型未知时再看前 8KB 中控制字符的比例。UTF-16 的文本中到处都是 NUL 字节，
+/// 因此编码是 UTF-16 时只看 MIME 类型
+fn is_binary(m: Option<&Mime>, body: &[u8], encoding: &'static Encoding) -> bool {
+    let sample = &body[..body.len().min(8192)];
+    let utf16 = encoding == UTF_16LE || encoding == UTF_16BE;
+    if !utf16 && sample.contains(&0) {
+        return true;
+    }
+    match m {
+        Some(m) if is_text(m) => false,
+        Some(m) if [mime::IMAGE, mime::AUDIO, mime::VIDEO, mime::FONT].contains(&m.type_()) => true,
+        Some(m) if m.essence_str() == "application/octet-stream" => true,
+        _ if utf16 => false,
+        _ => {
+            // 制表、换行、换页和 ESC 在文本中也很常见，不算作控制字符
+            let control = sample
+                .iter()
+                .filter(|&&b| b < 0x20 && !b"\t\n\r\x0c\x1b".contains(&b))
+                .count();
+            control * 10 > sample.len()
+        }
+    }
+}
+
+fn is_text(m: &Mime) -> bool {
+    m.type_() == mime::TEXT
+        || matches!(m.suffix().map(|s| s.as_str()), Some("json" | "xml"))
+        || matches!(
+            m.subtype().as_str(),
+            "json" | "xml" | "javascript" | "ecmascript" | "x-www-form-urlencoded" | "yaml"
+                | "x-yaml" | "x-ndjson" | "graphql"
+        )
+}
+
+/// 逐行读取并打印响应的 body，每打印一行就刷新一次 stdout，用于日志、进度这类长时间不断开的响应。
+/// 逐行 JSON（NDJSON）的每一行都是完整的 JSON，因此同样按照 JSON 高亮。
+/// 是否是二进制数据以及 body 的编码都根据收到的第一块数据来判断
+async fn stream_body(
+    out: &mut impl Write,
+    resp: Response,
+    m: Option<Mime>,
+    opts: &PrintOptions,
+    charset: Option<&'static Encoding>,
+) -> Result<()> {
+    let len = resp.content_length();
+    let mut stream = resp.bytes_stream();
+    let first = match stream.next().await {
+        Some(chunk) => chunk?,
+        None => return Ok(()),
+    };
+    let encoding = charset::detect(&first, m.as_ref(), charset);
+    if is_binary(m.as_ref(), &first, encoding) {
+        if !opts.binary {
+            return Ok(print_binary_note(out, m.as_ref(), len)?);
+        }
+        out.write_all(&first)?;
+        while let Some(chunk) = stream.next().await {
+            out.write_all(&chunk?)?;
+        }
+        return Ok(out.flush()?);
+    }
+
+    let formatter = formatters::registry().find(m.as_ref());
+    // 高亮的状态在行与行之间是延续的，因此整个 body 共用一个 HighlightLines
+    let ps = syntax_set();
+    let mut highlighter = opts.colors.and_then(|depth| {
+        let syntax = ps.find_syntax_by_extension(formatter.syntax()?)?;
+        Some((HighlightLines::new(syntax, &opts.theme), depth))
+    });
+    let mut print_line = |line: &str| -> io::Result<()> {
+        // 排版之后行尾的换行可能会丢失，因此先去掉换行符，排版之后再加上
+        let line = if opts.reformat {
+            formatter.format(line.trim_end_matches(['\r', '\n']), &opts.format) + "\n"
+        } else if line.ends_with('\n') {
+            line.to_string()
+        } else {
+            format!("{}\n", line)
+        };
+        match highlighter.as_mut() {
+            Some((h, depth)) if line.len() <= opts.highlight_limit => {
+                for line in LinesWithEndings::from(&line) {
+                    let ranges: Vec<(Style, &str)> = h.highlight(line, ps);
+                    write!(out, "{}\x1b[0m", terminal::escaped(&ranges[..], *depth))?;
+                }
+            }
+            _ => write!(out, "{}", line.cyan())?,
+        }
+        out.flush()
+    };
+
+    // 先解码再按行切分，Decoder 会保留被切断在两块数据之间的多字节字符
+    let mut decoder = encoding.new_decoder();
+    let mut text = String::new();
+    let mut chunk = Some(first);
+    while let Some(bytes) = chunk {
+        let max = decoder.max_utf8_buffer_length(bytes.len()).unwrap_or(bytes.len() * 3 + 16);
+        text.reserve(max);
+        // text 中剩下的都是上一块数据之后不完整的行，只需要在新解码出来的部分中查找换行
+        let scanned = text.len();
+        let _ = decoder.decode_to_string(&bytes, &mut text, false);
+        if let Some(pos) = text[scanned..].rfind('\n') {
+            let end = scanned + pos + 1;
+            for line in text[..end].split_inclusive('\n') {
+                print_line(line)?;
+            }
+            text.drain(..end);
+        }
+        chunk = stream.next().await.transpose()?;
+    }
+    text.reserve(decoder.max_utf8_buffer_length(0).unwrap_or(16));
+    let _ = decoder.decode_to_string(&[], &mut text, true);
+    // 最后一行可能没有换行符
+    if !text.is_empty() {
+        print_line(&text)?;
+    }
+    Ok(())
+}
+
+/// 按照 HTTP/1.1 的格式打印即将发送的请求，请求行属于请求头
+pub fn print_request(req: &Request, opts: &PrintOptions) -> Result<()> {
+    if !opts.request() {
+        return Ok(());
+    }
+    let mut out = io::stdout().lock();
+    if opts.request_headers {
+        print_request_headers(&mut out, req, opts)?;
+    }
+    // 请求头最后已经有一个空行了，只有打印了请求的 body 时，后面还要打印响应的话才需要再用一个空行隔开
+    if opts.request_body && req.body().is_some() {
+        print_request_body(&mut out, req, opts)?;
+        if opts.response() {
+            writeln!(out)?;
+        }
+    }
+    Ok(())
+}
+
+fn print_request_headers(out: &mut impl Write, req: &Request, opts: &PrintOptions) -> io::Result<()> {
+    // 请求行中只有 path 和 query，主机放在 Host 头中
+    let url = req.url();
+    let target = match url.query() {
+        Some(query) => format!("{}?{}", url.path(), query),
+        None => url.path().to_string(),
+    };
+    let line = opts.paint(&format!("{} {} HTTP/1.1", req.method(), target), |p| p.ok);
+    writeln!(out, "{}", line)?;
+
+    // Host 和 Content-Length 是在发送时由底层的 hyper 加上的，这里补上它们，让打印的内容和实际发送的一致
+    let mut headers = req.headers().clone();
+    if let Ok(host) = host(url).parse() {
+        headers.insert(header::HOST, host);
+    }
+    if let Some(body) = req.body().and_then(|b| b.as_bytes()) {
+        headers.insert(header::CONTENT_LENGTH, body.len().into());
+    }
+    print_headers(out, &headers, opts)
+}
+
+/// 调用者保证请求有 body
+fn print_request_body(out: &mut impl Write, req: &Request, opts: &PrintOptions) -> io::Result<()> {
+    match req.body().and_then(|b| b.as_bytes()) {
+        Some(body) => print_body_bytes(out, get_content_type(req.headers()), body, opts, None),
+        // multipart 上传文件时 body 是一个流，发送之前无法得到它的内容，只有文本字段时已经拼成了字节
+        None => writeln!(out, "{}", "(streamed body is not shown)".cyan()),
+    }
+}
+
+fn host(url: &Url) -> String {
+    let host = url.host_str().unwrap_or_default();
+    match url.port() {
+        Some(port) => format!("{}:{}", host, port),
+        None => host.to_string(),
+    }
+}
+
+/// 打印响应的状态行和响应头
+pub fn print_resp_headers(resp: &Response, opts: &PrintOptions) -> Result<()> {
+    Ok(write_resp_headers(&mut io::stdout().lock(), resp, opts)?)
+}
+
+fn write_resp_headers(out: &mut impl Write, resp: &Response, opts: &PrintOptions) -> io::Result<()> {
+    if opts.response_headers {
+        print_status(out, resp, opts)?;
+        print_headers(out, resp.headers(), opts)?;
+    }
+    Ok(())
+}
+
+/// 打印响应，started 是开始发送请求的时间，用于打印 m 中的耗时。
+/// stream 为 true 时边接收边打印 body，而不是等整个 body 接收完
+pub async fn print_resp(
+    resp: Response,
+    method: &Method,
+    opts: &PrintOptions,
+    started: Instant,
+    stream: bool,
+) -> Result<()> {
+    let mut out = io::stdout().lock();
+    write_resp_headers(&mut out, &resp, opts)?;
+    // HEAD 请求的响应没有 body，不需要再打印
+    if opts.response_body && method != Method::HEAD {
+        let content_type = get_content_type(resp.headers());
+        // --response-mime 中没有 charset 时只替换类型，服务器在 Content-Type 中声明的 charset 仍然有效
+        let charset = opts
+            .response_charset
+            .or_else(|| charset::from_mime(opts.response_mime.as_ref()?))
+            .or_else(|| charset::from_mime(content_type.as_ref()?));
+        let mime = opts.response_mime.clone().or(content_type);
+        if stream {
+            stream_body(&mut out, resp, mime, opts, charset).await?;
+        } else {
+            let body = resp.bytes().await?;
+            print_body_bytes(&mut out, mime, &body, opts, charset)?;
+        }
+    }
+    if opts.meta {
+        writeln!(out, "{}", format!("Elapsed time: {:.3}s", started.elapsed().as_secs_f64()).cyan())?;
+    }
+    Ok(())
+}
+
+/// 服务器返回的 Content-Type 无法解析时当作类型未知
+fn get_content_type(headers: &HeaderMap) -> Option<Mime> {
+    headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.parse().ok())
+}
@@ -0,0 +1,101 @@
+use std::{env, fmt::Write};
+use syntect::{
+    highlighting::{Color, Style},
+    util::as_24_bit_terminal_escaped,
+};
+
+// 终端的颜色支持。是否使用颜色由 --pretty、NO_COLOR、FORCE_COLOR 以及 stdout 是不是终端共同决定，
+// 颜色的种类则根据 COLORTERM 和 TERM 判断，不支持 24 位真彩色的终端会退回到 256 色或者 8 色
+
+/// 终端支持的颜色数量
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColorDepth {
+    Ansi8,
+    Ansi256,
+    TrueColor,
+}
+
+impl ColorDepth {
+    /// 和 colored 一样，COLORTERM 为 truecolor 或 24bit 时使用真彩色
+    pub fn detect() -> Self {
+        let colorterm = env::var("COLORTERM").unwrap_or_default();
+        let term = env::var("TERM").unwrap_or_default();
+        if colorterm == "truecolor" || colorterm == "24bit" {
+            Self::TrueColor
+        } else if term.contains("256color") {
+            Self::Ansi256
+        } else {
+            Self::Ansi8
+        }
+    }
+}
+
+/// 没有指定 --pretty 时是否使用颜色：设置了 NO_COLOR 时不使用颜色，否则设置了 FORCE_COLOR 时使用颜色，
+/// 都没有设置时只在终端中使用颜色。值为空或者 FORCE_COLOR=0 时视为没有设置。
+/// 显式指定的 --pretty 优先于这两个环境变量
+pub fn use_colors(is_terminal: bool) -> bool {
+    if env::var("NO_COLOR").is_ok_and(|v| !v.is_empty()) {
+        false
+    } else if env::var("FORCE_COLOR").is_ok_and(|v| !v.is_empty() && v != "0" && v != "false") {
+        true
+    } else {
+        is_terminal
+    }
+}
+
+/// 把高亮之后的文本转换成终端的转义序列，真彩色时连同主题的背景色一起输出，否则只输出前景色
+pub fn escaped(ranges: &[(Style, &str)], depth: ColorDepth) -> String {
+    if depth == ColorDepth::TrueColor {
+        return as_24_bit_terminal_escaped(ranges, true);
+    }
+    let mut s = String::new();
+    for (style, text) in ranges {
+        let _ = write!(s, "\x1b[{}m{}", foreground(style.foreground, depth), text);
+    }
+    s
+}
+
+/// 用一种颜色输出一段文本，用于状态行和头部
+pub fn paint(text: &str, color: Color, depth: ColorDepth) -> String {
+    format!("\x1b[{}m{}\x1b[0m", foreground(color, depth), text)
+}
+
+/// 前景色的 SGR 参数
+fn foreground(c: Color, depth: ColorDepth) -> String {
+    match depth {
+        ColorDepth::TrueColor => format!("38;2;{};{};{}", c.r, c.g, c.b),
+        ColorDepth::Ansi256 => format!("38;5;{}", ansi256(c)),
+        ColorDepth::Ansi8 => (30 + ansi8(c)).to_string(),
+    }
+}
+
+/// xterm 256 色中最接近的颜色，灰色使用 232-255 的灰度，其它使用 16-231 的 6x6x6 色块
+fn ansi256(c: Color) -> u8 {
+    if c.r == c.g && c.g == c.b {
+        return match c.r {
+            0..=7 => 16,
+            249..=255 => 231,
+            v => 232 + ((v - 8) as u16 * 23 / 240) as u8,
+        };
+    }
+    let level = |v: u8| ((v as u16 * 5 + 127) / 255) as u8;
+    16 + 36 * level(c.r) + 6 * level(c.g) + level(c.b)
+}
+
+/// 8 种基本颜色中色调最接近的颜色的编号，0 为黑色，7 为白色。主题中的颜色大多是比较柔和的，
+/// 按照 RGB 距离取最近的颜色时几乎都会变成白色，因此这里只看哪些分量明显高于其它分量
+pub fn ansi8(c: Color) -> u8 {
+    let max = c.r.max(c.g).max(c.b);
+    let min = c.r.min(c.g).min(c.b);
+    // 接近灰色的颜色
+    if max - min < 32 {
+        return if max < 64 { 0 } else { 7 };
+    }
+    let threshold = min + (max - min) / 2;
+    [c.r, c.g, c.b]
+        .iter()
+        .enumerate()
+        .filter(|(_, &v)| v > threshold)
+        .map(|(i, _)| 1 << i)
+        .sum()
+}
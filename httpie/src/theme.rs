@@ -0,0 +1,138 @@
+use std::{
+    env, fs,
+    io::{self, Write},
+    path::PathBuf,
+    sync::OnceLock,
+};
+use anyhow::{anyhow, Result};
+use syntect::{
+    highlighting::{Color, Highlighter, Theme, ThemeSet},
+    parsing::Scope,
+};
+use crate::terminal;
+
+// 高亮用的主题。除了 syntect 自带的主题之外，还会加载配置目录中 themes/ 下的 .tmTheme 文件，
+// 文件名（不含扩展名）就是主题的名字，可以用 --style 选择
+
+/// 没有指定 --style 时使用的主题
+pub const DEFAULT_STYLE: &str = "base16-ocean.dark";
+
+/// 自定义主题所在的目录：$HTTPIE_CONFIG_DIR/themes，或者 $XDG_CONFIG_HOME/httpie/themes，
+/// 缺省为 ~/.config/httpie/themes，Windows 上是 %APPDATA%\httpie\themes
+fn themes_dir() -> Option<PathBuf> {
+    let config = env::var_os("HTTPIE_CONFIG_DIR").map(PathBuf::from).or_else(|| {
+        let base = env::var_os("XDG_CONFIG_HOME")
+            .map(PathBuf::from)
+            .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))
+            .or_else(|| env::var_os("APPDATA").map(PathBuf::from))?;
+        Some(base.join("httpie"))
+    })?;
+    Some(config.join("themes"))
+}
+
+/// 自带的主题以及配置目录中的自定义主题，自定义主题可以覆盖同名的自带主题。
+/// 它们只在第一次使用时加载一次，自带的主题来自 syntect 中预先编译好的 dump
+pub fn themes() -> &'static ThemeSet {
+    static THEMES: OnceLock<ThemeSet> = OnceLock::new();
+    THEMES.get_or_init(load_themes)
+}
+
+/// 无法读取的自定义主题只打印一个警告然后跳过，一个坏掉的文件不应该让所有的请求都失败
+fn load_themes() -> ThemeSet {
+    let mut themes = ThemeSet::load_defaults();
+    let Some(entries) = themes_dir().and_then(|dir| fs::read_dir(dir).ok()) else {
+        return themes;
+    };
+    for path in entries.filter_map(|e| e.ok()).map(|e| e.path()) {
+        let Some(name) = path
+            .file_stem()
+            .filter(|_| path.extension().is_some_and(|ext| ext == "tmTheme"))
+            .map(|name| name.to_string_lossy().into_owned())
+        else {
+            continue;
+        };
+        match ThemeSet::get_theme(&path) {
+            Ok(theme) => {
+                themes.themes.insert(name, theme);
+            }
+            Err(e) => eprintln!("Warning: skipping theme {}: {}", path.display(), e),
+        }
+    }
+    themes
+}
+
+/// 按照名字找到一个主题
+pub fn load(name: &str) -> Result<Theme> {
+    themes()
+        .themes
+        .get(name)
+        .cloned()
+        .ok_or_else(|| anyhow!("unknown style `{}`, use --list-styles to see all styles", name))
+}
+
+/// --list-styles，按字母顺序打印所有可用的主题
+pub fn list_styles() -> Result<()> {
+    let mut out = io::stdout().lock();
+    for name in themes().themes.keys() {
+        if name == DEFAULT_STYLE {
+            writeln!(out, "{} (default)", name)?;
+        } else {
+            writeln!(out, "{}", name)?;
+        }
+    }
+    if let Some(dir) = themes_dir() {
+        writeln!(out, "\nCustom .tmTheme files are loaded from {}", dir.display())?;
+    }
+    Ok(())
+}
+
+/// 状态行和头部使用的颜色，从主题中和它们意思相近的语法元素中取得，
+/// 这样它们和高亮之后的 body 在同一个终端背景下都是可读的
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Palette {
+    /// 请求行以及 2xx、3xx 的状态行
+    pub ok: Color,
+    /// 4xx、5xx 的状态行
+    pub error: Color,
+    /// 头部的名字
+    pub name: Color,
+}
+
+impl Default for Palette {
+    fn default() -> Self {
+        let rgb = |r, g, b| Color { r, g, b, a: 0xff };
+        Self {
+            ok: rgb(0, 205, 0),
+            error: rgb(205, 0, 0),
+            name: rgb(0, 205, 205),
+        }
+    }
+}
+
+impl Palette {
+    pub fn from_theme(theme: &Theme) -> Self {
+        let default = Self::default();
+        let highlighter = Highlighter::new(theme);
+        let foreground = highlighter.get_default().foreground;
+        // 依次尝试这些 scope，跳过主题中没有为它们单独设置颜色的
+        let colors = |scopes: &[&str]| -> Vec<Color> {
+            scopes
+                .iter()
+                .filter_map(|s| Scope::new(s).ok())
+                .map(|scope| highlighter.style_for_stack(&[scope]).foreground)
+                .filter(|c| *c != foreground)
+                .collect()
+        };
+        let ok = colors(&["string", "markup.inserted"]).first().copied().unwrap_or(default.ok);
+        let error = colors(&["markup.deleted", "invalid", "keyword.control"])
+            .first()
+            .copied()
+            .unwrap_or(default.error);
+        // 头部的名字不能和错误的状态行是同一种颜色，在只有 8 种颜色的终端中也要能区分开
+        let name = colors(&["entity.other.attribute-name", "keyword", "entity.name.function", "support.function"])
+            .into_iter()
+            .find(|c| terminal::ansi8(*c) != terminal::ansi8(error))
+            .unwrap_or(default.name);
+        Self { ok, error, name }
+    }
+}
@@ -0,0 +1,147 @@
+use std::{
+    io::{Read, Write},
+    net::TcpListener,
+    process::{Command, Stdio},
+    thread,
+};
+
+// 用一个本地的 TCP 服务器返回各种奇怪的响应，确保 httpie 能正常打印它们而不会 panic
+
+/// 在随机端口上启动一个只处理一次请求的服务器，返回它的 URL。
+/// 响应由头部和 body 组成，Content-Length 和 Connection 会自动加上
+fn serve(headers: &[u8], body: &[u8]) -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let mut resp = b"HTTP/1.1 200 OK\r\n".to_vec();
+    resp.extend_from_slice(headers);
+    resp.extend_from_slice(format!("Content-Length: {}\r\nConnection: close\r\n\r\n", body.len()).as_bytes());
+    resp.extend_from_slice(body);
+    thread::spawn(move || {
+        let (mut stream, _) = listener.accept().unwrap();
+        // 读完请求头再返回响应，请求都是没有 body 的 GET
+        let mut req = Vec::new();
+        let mut buf = [0; 1024];
+        while !req.windows(4).any(|w| w == b"\r\n\r\n") {
+            let n = stream.read(&mut buf).unwrap();
+            if n == 0 {
+                break;
+            }
+            req.extend_from_slice(&buf[..n]);
+        }
+        stream.write_all(&resp).unwrap();
+    });
+    format!("http://{}/", addr)
+}
+
+/// 以强制使用颜色和排版的方式请求 url，返回去掉颜色之后的输出
+fn httpie(url: &str) -> String {
+    let output = Command::new(env!("CARGO_BIN_EXE_httpie"))
+        .args(["-I", "--pretty=all", "--print=hb", url])
+        .env("COLORTERM", "truecolor")
+        .output()
+        .unwrap();
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(output.status.success(), "httpie failed: {}", stderr);
+    strip_ansi(&String::from_utf8_lossy(&output.stdout))
+}
+
+fn strip_ansi(s: &str) -> String {
+    let mut out = String::new();
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        if c == '\x1b' {
+            // 跳过 ESC [ ... m
+            chars.by_ref().find(|c| c.is_ascii_alphabetic());
+        } else {
+            out.push(c);
+        }
+    }
+    out
+}
+
+#[test]
+fn unparsable_content_type_is_treated_as_unknown() {
+    let url = serve(b"Content-Type: this is not a mime type\r\n", b"hello");
+    let out = httpie(&url);
+    assert!(out.contains("content-type: this is not a mime type"));
+    assert!(out.contains("hello"));
+}
+
+#[test]
+fn non_ascii_content_type_is_treated_as_unknown() {
+    let url = serve(b"Content-Type: text/plain; charset=\xff\xfe\r\n", b"hello");
+    let out = httpie(&url);
+    assert!(out.contains("content-type: text/plain; charset=\\xff\\xfe"));
+    assert!(out.contains("hello"));
+}
+
+#[test]
+fn non_utf8_header_value_is_escaped() {
+    let url = serve(b"Content-Type: text/plain\r\nX-Name: caf\xe9 \xc3\x28\r\n", b"ok");
+    let out = httpie(&url);
+    assert!(out.contains("x-name: caf\\xe9 \\xc3("));
+}
+
+#[test]
+fn invalid_json_is_printed_as_is() {
+    let url = serve(b"Content-Type: application/json\r\n", b"{\"oops\": ");
+    let out = httpie(&url);
+    assert!(out.contains("{\"oops\": "));
+}
+
+#[test]
+fn malformed_xml_is_printed_as_is() {
+    let url = serve(b"Content-Type: application/xml\r\n", b"<a><b>text</a>");
+    let out = httpie(&url);
+    assert!(out.contains("<a><b>text</a>"));
+}
+
+#[test]
+fn every_highlighted_type_is_printed() {
+    // 第三项是排版之后 body 中应该出现的内容，头部总是会被打印，因此不能只检查输出是否为空
+    let bodies: [(&[u8], &[u8], &str); 6] = [
+        (b"application/problem+json", b"{\"b\":1,\"a\":2}", "\"a\": 2"),
+        (b"application/atom+xml", b"<feed><entry/></feed>", "<entry/>"),
+        (b"text/html; charset=utf-8", b"<p>hi</p>", "<p>hi</p>"),
+        (b"text/css", b"p { color: red }", "color: red"),
+        (b"application/javascript", b"let a = 1;", "let a = 1;"),
+        (b"application/yaml", b"a: 1", "a: 1"),
+    ];
+    for (mime, body, expected) in bodies {
+        let headers = [b"Content-Type: ", mime, b"\r\n"].concat();
+        let out = httpie(&serve(&headers, body));
+        assert!(
+            out.contains(expected),
+            "body of {} not printed: {}",
+            String::from_utf8_lossy(mime),
+            out
+        );
+    }
+}
+
+#[test]
+fn json_is_reformatted_with_sorted_keys() {
+    let url = serve(b"Content-Type: application/json\r\n", b"{\"b\":1,\"a\":[true]}");
+    let out = httpie(&url);
+    assert!(out.contains("{\n    \"a\": [\n        true\n    ],\n    \"b\": 1\n}"));
+}
+
+#[test]
+fn closed_stdout_exits_cleanly() {
+    // body 比管道的缓冲区大得多，读一点就关闭管道，就像 `httpie url | head -2`
+    let body = "line\n".repeat(1 << 20);
+    let url = serve(b"Content-Type: text/plain\r\n", body.as_bytes());
+    let mut child = Command::new(env!("CARGO_BIN_EXE_httpie"))
+        .args(["-I", "--pretty=none", &url])
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let mut stdout = child.stdout.take().unwrap();
+    stdout.read_exact(&mut [0; 16]).unwrap();
+    drop(stdout);
+    let output = child.wait_with_output().unwrap();
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(output.status.success(), "httpie failed: {}", stderr);
+    assert!(!stderr.contains("panicked"), "{}", stderr);
+}